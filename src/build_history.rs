@@ -0,0 +1,346 @@
+//! build_history.rs
+//!
+//! Model the build history of the BuildDistributionPipeline, as reported by the
+//! Jenkins json api. Each build records the parameters it was invoked with (project,
+//! version, flavor, platform, etc), which allows us to determine the last result for
+//! a particular distribution.
+use crate::{BuildRequest, Platform, RemoteBuildError};
+use serde::Deserialize;
+use std::{collections::HashMap, fmt};
+
+/// The `tree` query used to retrieve the build history from jenkins. Jenkins caps
+/// `builds` at the most recent 100, so we ask for `allBuilds` instead.
+pub const BUILD_HISTORY_TREE: &str =
+    "allBuilds[number,result,building,timestamp,actions[parameters[name,value]]]";
+
+/// The result of a jenkins build
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BuildResult {
+    Success,
+    Unstable,
+    Failure,
+    Aborted,
+    NotBuilt,
+    InProgress,
+    Unknown(String),
+}
+
+impl BuildResult {
+    /// Did the build produce a distribution?
+    pub fn is_success(&self) -> bool {
+        *self == BuildResult::Success
+    }
+
+    /// Did the build fail?
+    pub fn is_failure(&self) -> bool {
+        matches!(self, BuildResult::Failure | BuildResult::Unstable | BuildResult::Aborted)
+    }
+}
+
+impl<'a> From<&'a str> for BuildResult {
+    fn from(value: &'a str) -> Self {
+        match value.to_uppercase().as_str() {
+            "SUCCESS" => BuildResult::Success,
+            "UNSTABLE" => BuildResult::Unstable,
+            "FAILURE" => BuildResult::Failure,
+            "ABORTED" => BuildResult::Aborted,
+            "NOT_BUILT" => BuildResult::NotBuilt,
+            _ => BuildResult::Unknown(value.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for BuildResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildResult::Success => write!(f, "SUCCESS"),
+            BuildResult::Unstable => write!(f, "UNSTABLE"),
+            BuildResult::Failure => write!(f, "FAILURE"),
+            BuildResult::Aborted => write!(f, "ABORTED"),
+            BuildResult::NotBuilt => write!(f, "NOT_BUILT"),
+            BuildResult::InProgress => write!(f, "IN PROGRESS"),
+            BuildResult::Unknown(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A build parameter, as reported by jenkins
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+pub struct JenkinsParameter {
+    pub name: String,
+    #[serde(default)]
+    pub value: serde_json::Value,
+}
+
+/// One of the actions associated with a build. We only care about the ParametersAction,
+/// however they all get serialized, and most of them do not have parameters.
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+pub struct JenkinsAction {
+    #[serde(default)]
+    pub parameters: Vec<JenkinsParameter>,
+}
+
+/// A single run of a jenkins job
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+pub struct JenkinsBuild {
+    pub number: u32,
+    #[serde(default)]
+    result: Option<String>,
+    #[serde(default)]
+    building: bool,
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default)]
+    actions: Vec<Option<JenkinsAction>>,
+}
+
+impl JenkinsBuild {
+    /// Retrieve the result of the build
+    pub fn result(&self) -> BuildResult {
+        if self.building {
+            return BuildResult::InProgress;
+        }
+        match self.result {
+            Some(ref result) => BuildResult::from(result.as_str()),
+            None => BuildResult::InProgress,
+        }
+    }
+
+    /// Retrieve the value of the named parameter as a string
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.actions
+            .iter()
+            .flatten()
+            .flat_map(|action| action.parameters.iter())
+            .find(|param| param.name == name)
+            .map(|param| match param.value {
+                serde_json::Value::String(ref value) => value.clone(),
+                ref value => value.to_string(),
+            })
+    }
+
+    /// Determine whether the build was invoked for the supplied distribution
+    pub fn is_for(&self, project: &str, version: &str, flavor: &str, platform: &Platform) -> bool {
+        self.param("project").as_deref() == Some(project)
+            && self.param("version").as_deref() == Some(version)
+            && self.param("flavor").as_deref() == Some(flavor)
+            && self.param("platform").map(|x| Platform::from(x.as_str())).as_ref() == Some(platform)
+    }
+
+    /// Reconstruct the BuildRequest which the build was invoked with
+    pub fn to_build_request(&self) -> Result<BuildRequest, RemoteBuildError> {
+        let param = |name: &str| {
+            self.param(name).ok_or_else(|| {
+                RemoteBuildError::EmptyError(format!(
+                    "build #{} is missing the {} parameter",
+                    self.number, name
+                ))
+            })
+        };
+        let mut request = BuildRequest::new(
+            param("project")?,
+            param("version")?,
+            param("flavor")?,
+            param("repo")?.as_str(),
+            param("scmType")?.as_str(),
+            param("platform")?.as_str(),
+        )?
+        .with_submodules(self.param("initSubmodules").as_deref() == Some("true"))
+        .with_subdirectory(self.param("subdirectory").filter(|x| !x.is_empty()));
+        // builds submitted with an explicit ref record it separately from the repo.
+        // The ref is reproduced as is, whether or not it is a tag.
+        if let (Some(kind), Some(name)) = (self.param("refType"), self.param("ref")) {
+            if !name.is_empty() {
+                request.ref_kind = kind.parse()?;
+                request.ref_name = name;
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// The build history of a jenkins job
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+pub struct BuildHistory {
+    #[serde(default, rename = "allBuilds", alias = "builds")]
+    builds: Vec<JenkinsBuild>,
+}
+
+impl BuildHistory {
+    /// Construct a BuildHistory from the json returned by the jenkins api
+    pub fn from_str(input: &str) -> Result<Self, RemoteBuildError> {
+        serde_json::from_str(input).map_err(RemoteBuildError::SerdeJsonError)
+    }
+
+    /// Retrieve all of the builds
+    pub fn builds(&self) -> &[JenkinsBuild] {
+        &self.builds
+    }
+
+    /// Retrieve the most recent build of the supplied distribution
+    pub fn latest(
+        &self,
+        project: &str,
+        version: &str,
+        flavor: &str,
+        platform: &Platform,
+    ) -> Option<&JenkinsBuild> {
+        self.builds
+            .iter()
+            .filter(|build| build.is_for(project, version, flavor, platform))
+            .max_by_key(|build| build.number)
+    }
+
+    /// Retrieve the most recent build of each of the distributions of the project and
+    /// version, for those distributions whose most recent build failed. The builds are
+    /// returned in ascending build number.
+    pub fn failed(&self, project: &str, version: &str) -> Vec<&JenkinsBuild> {
+        let mut latest: HashMap<(String, String), &JenkinsBuild> = HashMap::new();
+        for build in self.builds.iter().filter(|build| {
+            build.param("project").as_deref() == Some(project)
+                && build.param("version").as_deref() == Some(version)
+        }) {
+            let key = (
+                build.param("flavor").unwrap_or_default(),
+                build.param("platform").unwrap_or_default(),
+            );
+            let entry = latest.entry(key).or_insert(build);
+            if build.number > entry.number {
+                *entry = build;
+            }
+        }
+        let mut failed = latest
+            .into_values()
+            .filter(|build| build.result().is_failure())
+            .collect::<Vec<&JenkinsBuild>>();
+        failed.sort_by_key(|build| build.number);
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HISTORY: &str = r#"{
+  "_class": "org.jenkinsci.plugins.workflow.job.WorkflowJob",
+  "allBuilds": [
+    {
+      "_class": "org.jenkinsci.plugins.workflow.job.WorkflowRun",
+      "actions": [
+        {
+          "_class": "hudson.model.ParametersAction",
+          "parameters": [
+            {"_class": "hudson.model.StringParameterValue", "name": "project", "value": "deferredpipeline"},
+            {"_class": "hudson.model.StringParameterValue", "name": "version", "value": "3.5.0"},
+            {"_class": "hudson.model.StringParameterValue", "name": "flavor", "value": "vray4.0.29259_for_maya2018"},
+            {"_class": "hudson.model.StringParameterValue", "name": "platform", "value": "cent7_64"}
+          ]
+        },
+        {"_class": "hudson.model.CauseAction"},
+        {}
+      ],
+      "building": false,
+      "number": 12,
+      "result": "FAILURE",
+      "timestamp": 1590000000000
+    },
+    {
+      "actions": [
+        {
+          "parameters": [
+            {"name": "project", "value": "deferredpipeline"},
+            {"name": "version", "value": "3.5.0"},
+            {"name": "flavor", "value": "vray4.0.29259_for_maya2018"},
+            {"name": "platform", "value": "cent7_64"}
+          ]
+        }
+      ],
+      "building": false,
+      "number": 10,
+      "result": "SUCCESS",
+      "timestamp": 1580000000000
+    },
+    {
+      "actions": [
+        {
+          "parameters": [
+            {"name": "project", "value": "deferredpipeline"},
+            {"name": "version", "value": "3.5.0"},
+            {"name": "flavor", "value": "^"},
+            {"name": "platform", "value": "cent7_64"}
+          ]
+        }
+      ],
+      "building": true,
+      "number": 13,
+      "result": null,
+      "timestamp": 1590000000001
+    }
+  ]
+}"#;
+
+    #[test]
+    fn can_create_history_from_str() {
+        let history = BuildHistory::from_str(HISTORY).unwrap();
+        assert_eq!(history.builds().len(), 3);
+        assert_eq!(history.builds()[0].param("flavor").as_deref(), Some("vray4.0.29259_for_maya2018"));
+    }
+
+    #[test]
+    fn can_find_latest_build() {
+        let history = BuildHistory::from_str(HISTORY).unwrap();
+        let latest = history
+            .latest("deferredpipeline", "3.5.0", "vray4.0.29259_for_maya2018", &Platform::Cent7)
+            .unwrap();
+        assert_eq!(latest.number, 12);
+        assert_eq!(latest.result(), BuildResult::Failure);
+
+        let latest = history
+            .latest("deferredpipeline", "3.5.0", "^", &Platform::Cent7)
+            .unwrap();
+        assert_eq!(latest.result(), BuildResult::InProgress);
+
+        assert!(history
+            .latest("deferredpipeline", "3.5.0", "^", &Platform::Cent6)
+            .is_none());
+    }
+
+    #[test]
+    fn can_find_failed_builds() {
+        let history = BuildHistory::from_str(HISTORY).unwrap();
+        let failed = history.failed("deferredpipeline", "3.5.0");
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].number, 12);
+        assert!(history.failed("deferredpipeline", "3.4.0").is_empty());
+    }
+
+    #[test]
+    fn can_reconstruct_build_request() {
+        let history = BuildHistory::from_str(
+            r#"{"builds": [
+                {"number": 4, "result": "FAILURE", "actions": [{"parameters": [
+                    {"name": "project", "value": "deferredpipeline"},
+                    {"name": "version", "value": "3.5.0"},
+                    {"name": "flavor", "value": "vray4_for_maya2016"},
+                    {"name": "repo", "value": "http://dd-git.d2.com/domains/lighting/deferredpipeline.git"},
+                    {"name": "scmType", "value": "git"},
+                    {"name": "platform", "value": "cent7_64"},
+                    {"name": "initSubmodules", "value": "true"},
+                    {"name": "subdirectory", "value": ""}
+                ]}]}
+            ]}"#,
+        )
+        .unwrap();
+        let request = history.builds()[0].to_build_request().unwrap();
+        assert_eq!(request.flavor, "vray4_for_maya2016");
+        assert_eq!(request.platform, Platform::Cent7);
+        assert_eq!(request.scm_type, crate::VcsSystem::Git);
+        assert!(request.init_submodules);
+        assert_eq!(request.subdirectory, None);
+        assert_eq!(request.ref_name, "3.5.0");
+        // the first build in HISTORY has no repo
+        let history = BuildHistory::from_str(HISTORY).unwrap();
+        assert!(history.builds()[0].to_build_request().is_err());
+    }
+}
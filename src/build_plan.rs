@@ -0,0 +1,255 @@
+//! build_plan.rs
+//!
+//! The BuildPlan models the matrix of platforms and flavors requested by the user,
+//! tracking which of the combinations are going to be submitted to the build server,
+//! and which are being skipped (and why).
+use crate::{build_history::BuildHistory, config::Constraint, Platform, RemoteBuildError};
+use log::debug;
+use prettytable::{cell, format, row, table, Table};
+use std::fmt;
+
+/// The reason an entry in the plan is not being submitted
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SkipReason {
+    /// The combination of platform and flavor has been ruled out by a constraint
+    Constraint(String),
+    /// The distribution has already been built successfully, by the supplied build number
+    Built(u32),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Constraint(_) => write!(f, "skipped (constraint)"),
+            SkipReason::Built(_) => write!(f, "skipped (built)"),
+        }
+    }
+}
+
+/// The status of an entry in the plan
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PlanStatus {
+    Submit,
+    Skipped(SkipReason),
+}
+
+impl PlanStatus {
+    /// Is the entry going to be submitted?
+    pub fn is_submit(&self) -> bool {
+        *self == PlanStatus::Submit
+    }
+}
+
+impl fmt::Display for PlanStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanStatus::Submit => write!(f, "submit"),
+            PlanStatus::Skipped(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// A single platform and flavor combination
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PlanEntry {
+    pub platform: Platform,
+    pub flavor: String,
+    pub status: PlanStatus,
+}
+
+/// The full matrix of platforms and flavors for a package and tag
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BuildPlan {
+    pub project: String,
+    pub version: String,
+    entries: Vec<PlanEntry>,
+}
+
+impl BuildPlan {
+    /// New up a BuildPlan consisting of the cross product of the supplied platforms and
+    /// flavors, all of which are initially slated for submission.
+    pub fn new(project: &str, version: &str, platforms: &[Platform], flavors: &[&str]) -> Self {
+        let mut entries = Vec::with_capacity(platforms.len() * flavors.len());
+        for platform in platforms {
+            for flavor in flavors {
+                entries.push(PlanEntry {
+                    platform: platform.clone(),
+                    flavor: flavor.to_string(),
+                    status: PlanStatus::Submit,
+                });
+            }
+        }
+        Self {
+            project: project.to_string(),
+            version: version.to_string(),
+            entries,
+        }
+    }
+
+    /// Skip any entries ruled out by one of the supplied constraints
+    pub fn apply_constraints(&mut self, constraints: &[Constraint]) -> Result<(), RemoteBuildError> {
+        for entry in self.entries.iter_mut().filter(|x| x.status.is_submit()) {
+            for constraint in constraints {
+                if constraint.excludes(&self.project, &entry.platform, &entry.flavor)? {
+                    debug!("constraint {:?} excludes {:?}", constraint, entry);
+                    entry.status = PlanStatus::Skipped(SkipReason::Constraint(constraint.reason()));
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip any entries whose most recent build in the supplied history succeeded
+    pub fn skip_built(&mut self, history: &BuildHistory) {
+        for entry in self.entries.iter_mut().filter(|x| x.status.is_submit()) {
+            if let Some(build) = history.latest(&self.project, &self.version, &entry.flavor, &entry.platform) {
+                if build.result().is_success() {
+                    debug!("build #{} already succeeded for {:?}", build.number, entry);
+                    entry.status = PlanStatus::Skipped(SkipReason::Built(build.number));
+                }
+            }
+        }
+    }
+
+    /// Retrieve the distinct platforms in the plan, in order
+    pub fn platforms(&self) -> Vec<&Platform> {
+        let mut platforms: Vec<&Platform> = Vec::new();
+        for entry in &self.entries {
+            if !platforms.contains(&&entry.platform) {
+                platforms.push(&entry.platform);
+            }
+        }
+        platforms
+    }
+
+    /// Retrieve the distinct flavors in the plan, in order
+    pub fn flavors(&self) -> Vec<&str> {
+        let mut flavors: Vec<&str> = Vec::new();
+        for entry in &self.entries {
+            if !flavors.contains(&entry.flavor.as_str()) {
+                flavors.push(&entry.flavor);
+            }
+        }
+        flavors
+    }
+
+    /// Retrieve all of the entries in the plan
+    pub fn entries(&self) -> &[PlanEntry] {
+        &self.entries
+    }
+
+    /// Retrieve the entries which are slated for submission
+    pub fn to_submit(&self) -> impl Iterator<Item = &PlanEntry> {
+        self.entries.iter().filter(|x| x.status.is_submit())
+    }
+
+    /// Retrieve the entries which are being skipped
+    pub fn skipped(&self) -> impl Iterator<Item = &PlanEntry> {
+        self.entries.iter().filter(|x| !x.status.is_submit())
+    }
+
+    /// Generate a table presenting the plan
+    pub fn table(&self) -> Table {
+        let mut table = table!([FYbH4c -> "Build Plan"]);
+        table.add_row(row![FYb -> "Platform", FYb -> "Flavor", FYb -> "Status", FYb -> "Reason"]);
+        for entry in &self.entries {
+            let reason = match entry.status {
+                PlanStatus::Skipped(SkipReason::Constraint(ref reason)) => reason.clone(),
+                PlanStatus::Skipped(SkipReason::Built(number)) => format!("build #{}", number),
+                PlanStatus::Submit => String::new(),
+            };
+            if entry.status.is_submit() {
+                table.add_row(row![
+                    Fw -> entry.platform.to_string().as_str(),
+                    Fw -> entry.flavor.as_str(),
+                    Fgb -> entry.status.to_string().as_str(),
+                    Fw -> reason.as_str()
+                ]);
+            } else {
+                table.add_row(row![
+                    Fw -> entry.platform.to_string().as_str(),
+                    Fw -> entry.flavor.as_str(),
+                    Fyb -> entry.status.to_string().as_str(),
+                    Fw -> reason.as_str()
+                ]);
+            }
+        }
+        table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(platform: &str, flavor: &str) -> Constraint {
+        Constraint {
+            platform: Some(platform.to_string()),
+            flavor: Some(flavor.to_string()),
+            ..Constraint::default()
+        }
+    }
+
+    #[test]
+    fn can_build_cross_product() {
+        let plan = BuildPlan::new(
+            "deferredpipeline",
+            "3.5.0",
+            &[Platform::Cent6, Platform::Cent7],
+            &["vray4_for_maya2016", "vray4_for_maya2018"],
+        );
+        assert_eq!(plan.entries().len(), 4);
+        assert_eq!(plan.to_submit().count(), 4);
+    }
+
+    #[test]
+    fn can_prune_with_constraints() {
+        let mut plan = BuildPlan::new(
+            "deferredpipeline",
+            "3.5.0",
+            &[Platform::Cent6, Platform::Cent7],
+            &["vray4_for_maya2016", "vray4_for_maya2018"],
+        );
+        plan.apply_constraints(&[constraint("cent7", "*maya2016")]).unwrap();
+        assert_eq!(plan.to_submit().count(), 3);
+        let skipped = plan.skipped().collect::<Vec<_>>();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].platform, Platform::Cent7);
+        assert_eq!(skipped[0].flavor, "vray4_for_maya2016");
+        assert_eq!(skipped[0].status.to_string(), "skipped (constraint)");
+    }
+
+    #[test]
+    fn can_skip_built_distributions() {
+        let history = BuildHistory::from_str(
+            r#"{"builds": [
+                {"number": 3, "result": "SUCCESS", "actions": [{"parameters": [
+                    {"name": "project", "value": "deferredpipeline"},
+                    {"name": "version", "value": "3.5.0"},
+                    {"name": "flavor", "value": "vray4_for_maya2018"},
+                    {"name": "platform", "value": "cent7_64"}
+                ]}]},
+                {"number": 4, "result": "FAILURE", "actions": [{"parameters": [
+                    {"name": "project", "value": "deferredpipeline"},
+                    {"name": "version", "value": "3.5.0"},
+                    {"name": "flavor", "value": "vray4_for_maya2016"},
+                    {"name": "platform", "value": "cent7_64"}
+                ]}]}
+            ]}"#,
+        )
+        .unwrap();
+        let mut plan = BuildPlan::new(
+            "deferredpipeline",
+            "3.5.0",
+            &[Platform::Cent7],
+            &["vray4_for_maya2016", "vray4_for_maya2018"],
+        );
+        plan.skip_built(&history);
+        let skipped = plan.skipped().collect::<Vec<_>>();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].flavor, "vray4_for_maya2018");
+        assert_eq!(skipped[0].status, PlanStatus::Skipped(SkipReason::Built(3)));
+    }
+}
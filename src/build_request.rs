@@ -4,9 +4,8 @@
 //! A BuildRequest models the data needed to trigger a build on jenkins. It includes the
 //! project, the version, the flavor, the repo, teh scm type
 //use crate::BuildParamType;
-use crate::{VcsSystem, Platform, RemoteBuildError};
-use url::{ParseError,Url};
-use std::str::FromStr;
+use crate::{VcsSystem, Platform, RemoteBuildError, RefKind, RepoRef};
+use url::Url;
 use log::debug;
 use crate::build_parameter::*;
 
@@ -68,12 +67,20 @@ pub struct BuildRequest {
     pub version: String,
     /// package flavor. "^" is vanilla
     pub flavor: String,
-    /// Url to the package's repository in version control
+    /// Url to the package's repository in version control, without a ref
     pub repo: Url,
+    /// The kind of ref within the repo to build (typically a tag)
+    pub ref_kind: RefKind,
+    /// The name of the ref within the repo to build
+    pub ref_name: String,
     /// The version control system that the package is stored in
     pub scm_type: VcsSystem,
     /// The os that the package is to be built for
     pub platform: Platform,
+    /// Whether submodules should be initialized when checking out the repo
+    pub init_submodules: bool,
+    /// The subdirectory of the repo which the package lives in, if not the root
+    pub subdirectory: Option<String>,
 }
 
 impl BuildRequest {
@@ -84,7 +91,7 @@ impl BuildRequest {
     /// * `project` - Name of the package, as a type which can be converted into a String.
     /// * `version` - Version of the package, which must also be an extant tag in the vcs.
     /// * `flavor`  - Specific flavor we are requesting be built.
-    /// * `repo`    - Url to the project.
+    /// * `repo`    - Url to the project, optionally with a ref fragment (eg `#tag=1.2.3`) which must agree with the version.
     /// * `scm_type` - The type of the Version Control System that the tagged project is checked in to.
     pub fn new<'a, T, P>(
         project: T,
@@ -93,23 +100,45 @@ impl BuildRequest {
         repo: &'a str,
         scm_type: impl Into<VcsSystem> + std::fmt::Debug,
         platform: P,
-    ) -> Result<Self, ParseError>
+    ) -> Result<Self, RemoteBuildError>
     where
         T: Into<String> + std::fmt::Debug,
         P: Into<Platform> + std::fmt::Debug,
     {
         debug!("BuildRequest::new({:?}, {:?}, {:?}, {:?}, {:?}, {:?})", project, version, flavor, repo, &scm_type, &platform);
-        let url = Url::from_str(repo)?;
+        let version = version.into();
+        let repo_ref = RepoRef::for_tag(repo, &version)?;
         Ok(Self {
             project: project.into(),
-            version: version.into(),
+            version,
             flavor: flavor.into(),
-            repo: url,
+            repo: repo_ref.url,
+            ref_kind: repo_ref.kind,
+            ref_name: repo_ref.name,
             scm_type: scm_type.into(),
             platform: platform.into(),
+            init_submodules: false,
+            subdirectory: None,
         })
     }
 
+    /// Retrieve the repo and ref being built
+    pub fn repo_ref(&self) -> RepoRef {
+        RepoRef::new(self.repo.clone(), self.ref_kind.clone(), self.ref_name.as_str())
+    }
+
+    /// Request that submodules be initialized when checking out the repo
+    pub fn with_submodules(mut self, init_submodules: bool) -> Self {
+        self.init_submodules = init_submodules;
+        self
+    }
+
+    /// Set the subdirectory of the repo which the package lives in
+    pub fn with_subdirectory<S: Into<String>>(mut self, subdirectory: Option<S>) -> Self {
+        self.subdirectory = subdirectory.map(|x| x.into());
+        self
+    }
+
     /// Generate a BuildParameters struct from a BuildRequest. The BuildParameters
     /// is json serializable and has the correct shape
     pub fn to_build_params(&self) -> BuildParameters {
@@ -118,60 +147,40 @@ impl BuildRequest {
         let version = BuildParameter::new("version", self.version.as_str());
         let flavor = BuildParameter::new("flavor", self.flavor.as_str());
         let repo = BuildParameter::new("repo", self.repo.clone()); //todo take 'a
+        let ref_kind = BuildParameter::new("refType", self.ref_kind.to_string());
+        let ref_name = BuildParameter::new("ref", self.ref_name.as_str());
         let scm_type = BuildParameter::new("scmType", self.scm_type.clone());
         let platform = BuildParameter::new("platform", self.platform.clone());
         // From Rohith:
         // upstream_workspace is not being used. pass an empty string for now
         let upstream_workspace = BuildParameter::new("upstream_workspace", String::new());
+        let init_submodules = BuildParameter::new("initSubmodules", self.init_submodules.to_string());
+        let subdirectory = BuildParameter::new(
+            "subdirectory", 
+            self.subdirectory.clone().unwrap_or_default()
+        );
 
         params.push(project);
         params.push(version);
         params.push(flavor);
         params.push(repo);
+        params.push(ref_kind);
+        params.push(ref_name);
         params.push(scm_type);
         params.push(platform);
         params.push(upstream_workspace);
+        params.push(init_submodules);
+        params.push(subdirectory);
         
         params
     }
 
-    // Construct a Vector of BuildRequest instances, one per flavor.
-    // The BuildRequest provides a method that produces a struct
-    // which is serializable into json in the form that Jenkins
-    // is looking for
-    pub fn build_requests(
-        //minifest: &Minifest,
-        name: &str,
-        version: &str,
-        repo: &str,
-        scm_type: &VcsSystem,
-        platform: &Platform,
-        flavors: &Vec<&str>,
-    ) -> Result<Vec<BuildRequest>, RemoteBuildError> {
-        
-        let mut build_reqs = Vec::with_capacity(flavors.len());
-        for flav in flavors {
-            let build_request = BuildRequest::new(
-                //minifest.name.as_str(),
-               // minifest.version.as_str(),
-               name, 
-               version,
-                flav,
-                repo,
-                scm_type,
-                platform,
-            )?;
-            debug!("adding build_request {:?} to build_reqs", &build_request);
-            build_reqs.push(build_request);
-        }
-        Ok(build_reqs)
-    }
-
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn can_build_req() {
@@ -185,8 +194,8 @@ mod tests {
         );
 
         assert_eq!(
-            req,
-            Ok(BuildRequest {
+            req.unwrap(),
+            BuildRequest {
                 project: "houdini_submission".to_string(),
                 version: "5.4.0".to_string(),
                 flavor: "^".to_string(),
@@ -195,8 +204,12 @@ mod tests {
                     "http://dd-svn.d2.com/svn/software/packages/houdini_submission"
                 )
                 .unwrap(),
+                ref_kind: RefKind::Tag,
+                ref_name: "5.4.0".to_string(),
                 platform: Platform::Cent6,
-            })
+                init_submodules: false,
+                subdirectory: None,
+            }
         )
     }
 
@@ -212,7 +225,36 @@ mod tests {
         );
         let reqf = req.unwrap().to_build_params();
         let j = serde_json::to_string(&reqf).unwrap();
-        assert_eq!(j, "{\"parameter\":[{\"name\":\"project\",\"value\":\"houdini_submission\"},{\"name\":\"version\",\"value\":\"5.4.0\"},{\"name\":\"flavor\",\"value\":\"^\"},{\"name\":\"repo\",\"value\":\"http://dd-svn.d2.com/svn/software/packages/houdini_submission\"},{\"name\":\"scmType\",\"value\":\"svn\"},{\"name\":\"platform\",\"value\":\"cent6_64\"},{\"name\":\"upstream_workspace\",\"value\":\"\"}]}");
+        assert_eq!(j, "{\"parameter\":[{\"name\":\"project\",\"value\":\"houdini_submission\"},{\"name\":\"version\",\"value\":\"5.4.0\"},{\"name\":\"flavor\",\"value\":\"^\"},{\"name\":\"repo\",\"value\":\"http://dd-svn.d2.com/svn/software/packages/houdini_submission\"},{\"name\":\"refType\",\"value\":\"tag\"},{\"name\":\"ref\",\"value\":\"5.4.0\"},{\"name\":\"scmType\",\"value\":\"svn\"},{\"name\":\"platform\",\"value\":\"cent6_64\"},{\"name\":\"upstream_workspace\",\"value\":\"\"},{\"name\":\"initSubmodules\",\"value\":\"false\"},{\"name\":\"subdirectory\",\"value\":\"\"}]}");
+    }
+
+    #[test]
+    fn can_serialize_submodules_and_subdirectory() {
+        let req = BuildRequest::new(
+            "animtools",
+            "1.2.3",
+            "^",
+            "ssh://git@dd-git.d2.com:2224/domains/animation/monorepo.git",
+            "git",
+            "cent7",
+        )
+        .unwrap()
+        .with_submodules(true)
+        .with_subdirectory(Some("animtools"));
+        let j = serde_json::to_string(&req.to_build_params()).unwrap();
+        assert!(j.ends_with("{\"name\":\"initSubmodules\",\"value\":\"true\"},{\"name\":\"subdirectory\",\"value\":\"animtools\"}]}"));
+    }
+
+    #[test]
+    fn can_separate_repo_and_ref() {
+        let link = "ssh://git@dd-git.d2.com:2224/domains/lighting/deferredpipeline.git#tag=3.5.0";
+        let req = BuildRequest::new("deferredpipeline", "3.5.0", "^", link, "git", "cent7").unwrap();
+        assert_eq!(req.repo.as_str(), "ssh://git@dd-git.d2.com:2224/domains/lighting/deferredpipeline.git");
+        assert_eq!(req.ref_kind, RefKind::Tag);
+        assert_eq!(req.ref_name, "3.5.0");
+        assert_eq!(req.repo_ref().to_string(), link);
+        // the link's tag must match the version
+        assert!(BuildRequest::new("deferredpipeline", "3.4.0", "^", link, "git", "cent7").is_err());
     }
 }
 
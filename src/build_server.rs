@@ -3,6 +3,7 @@
 //! Provides the BuildServer struct, which is used to connect to
 //! the build server and request a remote build from it.
 use crate::{
+    build_history::{BuildHistory, BUILD_HISTORY_TREE},
     constants::*, 
     errors::RemoteBuildError,
     utils::UserBuildRequest,
@@ -93,6 +94,82 @@ impl BuildServer {
         }
     }
 
+    /// The route which triggers a scan of the package's job on the build server, via
+    /// which newly pushed tags are discovered.
+    pub fn scan_route(&self, package: &str) -> Option<Url> {
+        let route = format!("job/Packages/job/{}/build?delay=0", package);
+        debug!("scan_route() route: {}", &route);
+        Url::from_str(
+            format!(
+                "http://{}:{}@{}.{}:{}/{}",
+                USERNAME, PASSWORD, self.host, self.domain, self.port, route
+            )
+            .as_str(),
+        )
+        .ok()
+    }
+
+    /// Ask the build server to scan the package's repository for tags
+    pub fn request_scan(&self, package: &str) -> Result<(), RemoteBuildError> {
+        let route = self
+            .scan_route(package)
+            .ok_or_else(|| RemoteBuildError::EmptyError("unable to unwrap scan route".into()))?;
+        reqwest::blocking::Client::new()
+            .post(route)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Has the build server discovered the package's tag? Tags are discovered by
+    /// scanning the package (see `request_scan`).
+    pub fn has_tag_job(&self, package: &str, tag: &str) -> Result<bool, RemoteBuildError> {
+        let route = self
+            .api_route(&format!("job/Packages/job/{}/job/{}", package, tag), "name")
+            .ok_or_else(|| RemoteBuildError::EmptyError("unable to unwrap api route".into()))?;
+        debug!("has_tag_job() route: {}", &route);
+        let response = reqwest::blocking::Client::new()
+            .get(route)
+            .basic_auth(USERNAME, Some(PASSWORD))
+            .send()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status()?;
+        Ok(true)
+    }
+
+    /// Generate the url to the json api for the supplied job route (eg `job/Plans/job/BuildDistributionPipeline`),
+    /// limiting the response to the supplied `tree`.
+    pub fn api_route(&self, job_route: &str, tree: &str) -> Option<Url> {
+        let mut url = Url::from_str(
+            format!(
+                "http://{}.{}:{}/{}/api/json",
+                self.host, self.domain, self.port, job_route
+            )
+            .as_str(),
+        )
+        .ok()?;
+        url.query_pairs_mut().append_pair("tree", tree);
+        Some(url)
+    }
+
+    /// Retrieve the build history of the BuildDistributionPipeline from the build server.
+    /// This includes the parameters each build was invoked with, as well as its result.
+    pub fn build_history(&self) -> Result<BuildHistory, RemoteBuildError> {
+        let route = self
+            .api_route(BUILD_JOB, BUILD_HISTORY_TREE)
+            .ok_or_else(|| RemoteBuildError::EmptyError("unable to unwrap api route".into()))?;
+        debug!("build_history() route: {}", &route);
+        let client = reqwest::blocking::Client::new();
+        let response = client
+            .get(route)
+            .basic_auth(USERNAME, Some(PASSWORD))
+            .send()?
+            .error_for_status()?;
+        BuildHistory::from_str(&response.text()?)
+    }
+
     /// Request a build from the build server, providing information per the
     /// req
     ///
@@ -3,8 +3,9 @@
 //! This module defines the cli interface for the command
 //! using the popular structopt crate
 
+use crate::RemoteBuildError;
 use structopt::StructOpt;
-//use std::path::PathBuf;
+use std::path::PathBuf;
 
 // value defined as default in structopt structure
 //const DEFAULT_PLATFORMS: &'static str = "cent7_64";
@@ -32,6 +33,11 @@ pub struct Opt {
     /// list. By default, pkg-build-remote will attempt to build all
     /// of the flavors defined in the manifest, if supplied. Otherwise,
     /// the vanilla flavor will be used.
+    ///
+    /// Each entry may be a flavor name, a glob (eg 'vray4*'), or a regular
+    /// expression prefixed with 're:' (eg 're:maya2018$'). Entries are matched
+    /// against the flavors packalaka reports for the tag, and an entry which
+    /// matches nothing is an error.
     #[structopt(short = "f", long = "flavours")]
     pub flavours: Option<String>,
 
@@ -41,31 +47,255 @@ pub struct Opt {
     #[structopt(long = "flavors")]
     pub flavors: Option<String>,
 
+    /// Optionally supply a comma separated list of flavor names, globs, or 're:'
+    /// prefixed regular expressions, identifying flavors which should not be built.
+    #[structopt(short = "x", long = "exclude-flavors", alias = "exclude-flavours")]
+    pub exclude_flavors: Option<String>,
+
+    /// Optionally restrict the flavors to those built for one of the supplied, comma
+    /// separated, host applications (eg maya2018 selects vray4.0.29259_for_maya2018).
+    #[structopt(long = "for")]
+    pub for_hosts: Option<String>,
+
+    /// Optionally restrict the flavors to those built with one of the supplied, comma
+    /// separated, renderers or plugins (eg vray4 selects vray4.0.29259_for_maya2018).
+    #[structopt(long = "with")]
+    pub with_renderers: Option<String>,
+
+    /// When the package has more than one source (eg it moved from svn to git), choose
+    /// the source to build from, by its index or by its repository url. By default,
+    /// active sources are chosen over retired ones.
+    #[structopt(long = "source")]
+    pub source: Option<String>,
+
+    /// When the package has more than one active source, prefer the one which uses
+    /// the supplied version control system (eg git).
+    #[structopt(long = "prefer-vcs")]
+    pub prefer_vcs: Option<String>,
+
     /// Specify the name of the package
     #[structopt(name = "PACKAGE")]
-    pub name: String,
+    pub name: Option<String>,
 
-    /// Specify the tag which you wish to build
+    /// Specify one or more tags which you wish to build. In addition to a specific tag,
+    /// each may be `latest`, `latest-stable`, or a version requirement such as `^3.5`,
+    /// `~3.4.1`, `3.4.*` or `>=3.4, <3.5`, which resolves to the highest matching tag
+    /// (or every matching tag, with --all-matching).
     #[structopt(name = "TAG")]
-    pub tag: String,
+    pub tags: Vec<String>,
+
+    /// Build every tag which satisfies a version requirement supplied as a TAG,
+    /// rather than just the highest.
+    #[structopt(long = "all-matching")]
+    pub all_matching: bool,
 
     /// Optionally supply a list of one or more, comma separated platforms to build for.
     /// This is case insensitive.
     #[structopt(short = "p", long = "platforms")]
     pub platforms: Option<String>,
 
+    /// Consider pre-release tags (eg 3.5.0.alpha2) when resolving the TAG, and when
+    /// retrieving the tag from packalaka. By default, pre-releases are skipped.
+    #[structopt(long = "include-pre")]
+    pub include_pre: bool,
+
+    /// Only build the flavors and platforms which do not already have a successful
+    /// distribution, according to the build server's BuildDistributionPipeline history.
+    #[structopt(short = "m", long = "missing-only")]
+    pub missing_only: bool,
+
     /// Provide verbose feedback to stdout
-    #[structopt(short = "v", long = "verbose")]
+    #[structopt(short = "v", long = "verbose", global = true)]
     pub verbose: bool,
 
     /// When set to true, pkg-build-remote will report on its choices,
     /// but will not actually execute a remote build. May be used to
     /// verify input to the command.
-    #[structopt(short = "d", long = "dry-run")]
+    #[structopt(short = "d", long = "dry-run", global = true)]
     pub dry_run: bool,
 
     /// Present a prompt allowing the user to decide whether to submit the job
     /// after reviewing relevant information.
-    #[structopt(short = "a", long = "ask")]
+    #[structopt(short = "a", long = "ask", global = true)]
     pub prompt: bool,
-}
\ No newline at end of file
+
+    /// Ignore any cached packalaka responses, replacing them with fresh ones
+    #[structopt(long = "refresh", global = true)]
+    pub refresh: bool,
+
+    /// Do not contact packalaka, relying entirely on cached responses
+    #[structopt(long = "offline", global = true)]
+    pub offline: bool,
+
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+impl Opt {
+    /// Retrieve the PACKAGE and TAG(s), which are required unless a subcommand 
+    /// has been supplied.
+    pub fn package_and_tags(&self) -> Result<(&str, &[String]), RemoteBuildError> {
+        match self.name.as_ref() {
+            Some(name) if !self.tags.is_empty() => Ok((name, &self.tags)),
+            _ => Err(RemoteBuildError::MissingArgument(
+                "PACKAGE and TAG are required. See --help for details".into(),
+            )),
+        }
+    }
+}
+
+/// Subcommands which may be used in place of PACKAGE and TAG
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Resubmit the distributions of a package and tag whose most recent
+    /// BuildDistributionPipeline run failed.
+    #[structopt(name = "rebuild-failed")]
+    RebuildFailed {
+        /// Specify the name of the package
+        #[structopt(name = "PACKAGE")]
+        name: String,
+
+        /// Specify the tag whose failed distributions you wish to rebuild
+        #[structopt(name = "TAG")]
+        tag: String,
+    },
+
+    /// List the tags of a package, along with their status and flavors
+    #[structopt(name = "tags")]
+    Tags {
+        /// Specify the name of the package
+        #[structopt(name = "PACKAGE")]
+        name: String,
+
+        /// Include pre-release tags
+        #[structopt(long = "include-pre")]
+        include_pre: bool,
+
+        /// Present the tags as json, rather than as a table
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Present a package's gpi record: its type and sources
+    #[structopt(name = "info")]
+    Info {
+        /// Specify the name of the package
+        #[structopt(name = "PACKAGE")]
+        name: String,
+
+        /// Present the record as json, rather than as a table
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Build the package in a local working copy, identifying the repository from
+    /// the working copy's vcs (git, svn, mercurial or perforce), and the name and version
+    /// from its manifest.
+    #[structopt(name = "local")]
+    Local(LocalOpt),
+
+    /// Release a new version of the package in a local git working copy: tag HEAD,
+    /// push the tag, have the build server scan for it, and build it.
+    #[structopt(name = "release")]
+    Release(ReleaseOpt),
+
+    /// Manage the cache of packalaka responses
+    #[structopt(name = "cache")]
+    Cache {
+        #[structopt(subcommand)]
+        cmd: CacheCommand,
+    },
+}
+
+/// Subcommands of the cache command
+#[derive(StructOpt, Debug)]
+pub enum CacheCommand {
+    /// Remove all of the cached packalaka responses
+    #[structopt(name = "clear")]
+    Clear,
+}
+
+/// Options for building from a local working copy
+#[derive(StructOpt, Debug)]
+pub struct LocalOpt {
+    /// Specify the name of the package, rather than reading it from the manifest.
+    /// The TAG must be supplied as well.
+    #[structopt(name = "PACKAGE")]
+    pub name: Option<String>,
+
+    /// Specify the tag to build, rather than reading the version from the manifest
+    #[structopt(name = "TAG")]
+    pub tag: Option<String>,
+
+    /// The path to the working copy. Defaults to the current directory
+    #[structopt(long = "project-path", parse(from_os_str))]
+    pub project_path: Option<PathBuf>,
+
+    /// Specify the vcs (git, svn, mercurial or perforce), rather than identifying it
+    /// from the working copy
+    #[structopt(long = "vcs")]
+    pub vcs: Option<String>,
+
+    /// Specify the url of the repository, rather than reading it from the working copy
+    #[structopt(long = "vcs-url")]
+    pub vcs_url: Option<String>,
+
+    /// Name the remote of the working copy to build from (eg origin). By default,
+    /// the first of the remotes preferred by the config (upstream, then origin) is used.
+    #[structopt(long = "remote", conflicts_with = "vcs_url")]
+    pub remote: Option<String>,
+
+    /// Build even though the working copy has uncommitted changes
+    #[structopt(long = "allow-dirty")]
+    pub allow_dirty: bool,
+
+    /// Build even though the working copy is not at the tag
+    #[structopt(long = "allow-head-mismatch")]
+    pub allow_head_mismatch: bool,
+
+    /// Build even though the version in the manifest is not the tag
+    #[structopt(long = "allow-version-mismatch")]
+    pub allow_version_mismatch: bool,
+
+    /// Optionally suppiy one or more flavours as a comma separated list. By default,
+    /// all of the flavors defined in the manifest are built.
+    #[structopt(short = "f", long = "flavours")]
+    pub flavours: Option<String>,
+
+    /// The American spelling of `flavours`
+    #[structopt(long = "flavors")]
+    pub flavors: Option<String>,
+
+    /// Optionally supply a list of one or more, comma separated platforms to build for.
+    #[structopt(short = "p", long = "platforms")]
+    pub platforms: Option<String>,
+}
+
+/// Options for releasing from a local git working copy
+#[derive(StructOpt, Debug)]
+pub struct ReleaseOpt {
+    /// The version to release, which becomes the name of the tag
+    #[structopt(name = "VERSION")]
+    pub version: String,
+
+    /// The path to the working copy. Defaults to the current directory
+    #[structopt(long = "project-path", parse(from_os_str))]
+    pub project_path: Option<PathBuf>,
+
+    /// Name the remote to push the tag to. By default, the first of the remotes
+    /// preferred by the config (upstream, then origin) is used.
+    #[structopt(long = "remote")]
+    pub remote: Option<String>,
+
+    /// The message of the annotated tag. Defaults to `Release <package> <version>`
+    #[structopt(short = "m", long = "message")]
+    pub message: Option<String>,
+
+    /// Release even though the working copy has uncommitted changes
+    #[structopt(long = "allow-dirty")]
+    pub allow_dirty: bool,
+
+    /// Release even though the version in the manifest is not the version being released
+    #[structopt(long = "allow-version-mismatch")]
+    pub allow_version_mismatch: bool,
+}
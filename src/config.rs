@@ -0,0 +1,320 @@
+//! config.rs
+//!
+//! Optional, user supplied configuration for pkg-build-remote, read from yaml.
+//! The config is looked up in `$PKG_BUILD_REMOTE_CONFIG`, falling back on
+//! `<config dir>/pkg-build-remote/config.yaml`. A missing config is not an error;
+//! we simply use the defaults.
+//!
+//! ```yaml
+//! cache:
+//!   ttl_secs: 3600
+//! constraints:
+//!   - platform: cent7
+//!     flavor: "*maya2016*"
+//!     reason: maya2016 is not supported on cent7
+//!   - package: deferredpipeline
+//!     flavor: "vray3*"
+//! remotes:
+//!   git_root: http://dd-git.d2.com
+//!   preferred: [upstream, origin]
+//!   rewrites:
+//!     - from: "git@dd-git-mirror.d2.com:"
+//!       to: $GIT_ROOT/
+//! ```
+use crate::{
+    constants::{GIT_ROOT, SVN_ROOT},
+    pattern::Pattern,
+    remote_url::UrlNormalizer,
+    Platform, RemoteBuildError,
+};
+use log::debug;
+use serde::Deserialize;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// Environment variable which may be used to point at a config file
+pub const CONFIG_VAR: &str = "PKG_BUILD_REMOTE_CONFIG";
+/// Name of the config file within the user's config directory
+pub const CONFIG_FILE: &str = "pkg-build-remote/config.yaml";
+/// How long packalaka responses are cached for, by default
+pub const DEFAULT_CACHE_TTL_SECS: u64 = 60 * 60;
+
+/// The configuration for pkg-build-remote
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Platform x flavor combinations which should never be submitted
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    /// Settings for the cache of packalaka responses
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// Settings for the repository urls handed to the build server
+    #[serde(default)]
+    pub remotes: RemotesConfig,
+}
+
+/// Settings for the cache of packalaka responses
+#[derive(Debug, Deserialize)]
+pub struct CacheConfig {
+    /// How long, in seconds, a cached response remains valid
+    #[serde(default = "CacheConfig::default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// The directory to cache responses in, if not the user's cache directory
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+}
+
+impl CacheConfig {
+    fn default_ttl_secs() -> u64 {
+        DEFAULT_CACHE_TTL_SECS
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_CACHE_TTL_SECS,
+            dir: None,
+        }
+    }
+}
+
+/// Settings for the repository urls handed to the build server. Rewrite rules
+/// replace a prefix of a repository url (eg to map an internal mirror to a host
+/// which the build server can reach). Rules may refer to `$GIT_ROOT` and
+/// `$SVN_ROOT`, which expand to `git_root` and `svn_root`. When building from a
+/// working copy without naming a remote, the first of the `preferred` remotes
+/// which exists is used.
+#[derive(Debug, Deserialize)]
+pub struct RemotesConfig {
+    #[serde(default = "RemotesConfig::default_git_root")]
+    pub git_root: String,
+    #[serde(default = "RemotesConfig::default_svn_root")]
+    pub svn_root: String,
+    #[serde(default)]
+    pub rewrites: Vec<RewriteRule>,
+    #[serde(default = "RemotesConfig::default_preferred")]
+    pub preferred: Vec<String>,
+}
+
+impl RemotesConfig {
+    fn default_git_root() -> String {
+        GIT_ROOT.to_string()
+    }
+
+    fn default_svn_root() -> String {
+        SVN_ROOT.to_string()
+    }
+
+    fn default_preferred() -> Vec<String> {
+        vec!["upstream".to_string(), "origin".to_string()]
+    }
+}
+
+impl Default for RemotesConfig {
+    fn default() -> Self {
+        Self {
+            git_root: GIT_ROOT.to_string(),
+            svn_root: SVN_ROOT.to_string(),
+            rewrites: Vec::new(),
+            preferred: Self::default_preferred(),
+        }
+    }
+}
+
+/// Replace the `from` prefix of a repository url with `to`. Either may be an
+/// scp style prefix (eg `git@dd-git.d2.com:`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RewriteRule {
+    pub from: String,
+    pub to: String,
+}
+
+impl Config {
+    /// Construct a Config from a yaml &str
+    pub fn from_str(input: &str) -> Result<Self, RemoteBuildError> {
+        let config: Config = serde_yaml::from_str(input)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load the config from disk, returning the default config if none exists.
+    pub fn load() -> Result<Self, RemoteBuildError> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::from_path(&path),
+            _ => {
+                debug!("no config found. using defaults");
+                Ok(Self::default())
+            }
+        }
+    }
+
+    /// Load the config from the supplied path.
+    pub fn from_path(path: &Path) -> Result<Self, RemoteBuildError> {
+        debug!("loading config from {:?}", path);
+        let contents = std::fs::read_to_string(path)?;
+        Self::from_str(&contents).map_err(|e| {
+            RemoteBuildError::ConfigError(format!("{}: {}", path.display(), e))
+        })
+    }
+
+    /// Retrieve the path to the config, if one can be determined
+    pub fn path() -> Option<PathBuf> {
+        if let Ok(path) = env::var(CONFIG_VAR) {
+            return Some(PathBuf::from(path));
+        }
+        dirs::config_dir().map(|mut path| {
+            path.push(CONFIG_FILE);
+            path
+        })
+    }
+
+    // make sure that the patterns in the config are valid
+    fn validate(&self) -> Result<(), RemoteBuildError> {
+        for constraint in &self.constraints {
+            constraint.validate()?;
+        }
+        UrlNormalizer::from_config(&self.remotes)?;
+        Ok(())
+    }
+}
+
+/// A rule identifying a combination of platform and flavor which cannot be
+/// built, either for a specific package (or packages), or globally when
+/// `package` is left out. Each field holds a `Pattern`, and the constraint
+/// applies when all of the supplied fields match.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Constraint {
+    #[serde(default)]
+    pub package: Option<String>,
+    #[serde(default)]
+    pub platform: Option<String>,
+    #[serde(default)]
+    pub flavor: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Constraint {
+    /// Determine whether the constraint rules out building the flavor of the
+    /// package on the platform.
+    pub fn excludes(
+        &self,
+        package: &str,
+        platform: &Platform,
+        flavor: &str,
+    ) -> Result<bool, RemoteBuildError> {
+        if let Some(ref package_pattern) = self.package {
+            if !Pattern::parse(package_pattern)?.matches(package) {
+                return Ok(false);
+            }
+        }
+        if let Some(ref platform_pattern) = self.platform {
+            // allow for the short form of the platform name (eg cent7)
+            let matches = match Pattern::parse(platform_pattern)? {
+                Pattern::Literal(lit) => Platform::from(lit.as_str()) == *platform,
+                pattern => pattern.matches(&platform.to_string()),
+            };
+            if !matches {
+                return Ok(false);
+            }
+        }
+        if let Some(ref flavor_pattern) = self.flavor {
+            if !Pattern::parse(flavor_pattern)?.matches(flavor) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Retrieve the reason for the constraint, or a generic description
+    /// if one has not been supplied.
+    pub fn reason(&self) -> String {
+        self.reason.clone().unwrap_or_else(|| {
+            format!(
+                "platform: {} flavor: {}",
+                self.platform.as_deref().unwrap_or("*"),
+                self.flavor.as_deref().unwrap_or("*")
+            )
+        })
+    }
+
+    fn validate(&self) -> Result<(), RemoteBuildError> {
+        if self.platform.is_none() && self.flavor.is_none() {
+            return Err(RemoteBuildError::ConfigError(
+                "constraints require a platform and/or flavor".into(),
+            ));
+        }
+        for pattern in [&self.package, &self.platform, &self.flavor].iter().copied().flatten() {
+            Pattern::parse(pattern)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+constraints:
+  - platform: cent7
+    flavor: "*maya2016*"
+    reason: maya2016 is not supported on cent7
+  - package: deferredpipeline
+    flavor: "vray3*"
+"#;
+
+    #[test]
+    fn can_create_config_from_str() {
+        let config = Config::from_str(CONFIG).unwrap();
+        assert_eq!(config.constraints.len(), 2);
+        assert_eq!(config.constraints[0].reason(), "maya2016 is not supported on cent7");
+        assert_eq!(config.cache.ttl_secs, DEFAULT_CACHE_TTL_SECS);
+    }
+
+    #[test]
+    fn can_configure_cache() {
+        let config = Config::from_str("cache:\n  ttl_secs: 60\n  dir: /tmp/packalaka\n").unwrap();
+        assert_eq!(config.cache.ttl_secs, 60);
+        assert_eq!(config.cache.dir, Some(PathBuf::from("/tmp/packalaka")));
+    }
+
+    #[test]
+    fn can_configure_remotes() {
+        let config = Config::from_str(
+            "remotes:\n  rewrites:\n    - from: \"git@dd-git-mirror.d2.com:\"\n      to: $GIT_ROOT/\n",
+        )
+        .unwrap();
+        assert_eq!(config.remotes.git_root, GIT_ROOT);
+        assert_eq!(config.remotes.rewrites.len(), 1);
+        assert_eq!(config.remotes.preferred, vec!["upstream", "origin"]);
+        assert!(Config::from_str("remotes:\n  rewrites:\n    - from: foo\n      to: $NOWHERE/\n").is_err());
+    }
+
+    #[test]
+    fn empty_config_is_valid() {
+        let config = Config::from_str("{}").unwrap();
+        assert!(config.constraints.is_empty());
+    }
+
+    #[test]
+    fn can_catch_constraint_without_platform_or_flavor() {
+        assert!(Config::from_str("constraints:\n  - package: foo\n").is_err());
+    }
+
+    #[test]
+    fn can_apply_constraints() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let global = &config.constraints[0];
+        assert!(global.excludes("foo", &Platform::Cent7, "vray4_for_maya2016").unwrap());
+        assert!(!global.excludes("foo", &Platform::Cent6, "vray4_for_maya2016").unwrap());
+        assert!(!global.excludes("foo", &Platform::Cent7, "vray4_for_maya2018").unwrap());
+
+        let package = &config.constraints[1];
+        assert!(package.excludes("deferredpipeline", &Platform::Cent6, "vray3.6_for_maya2018").unwrap());
+        assert!(!package.excludes("houdini_submission", &Platform::Cent6, "vray3.6_for_maya2018").unwrap());
+    }
+}
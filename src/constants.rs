@@ -2,12 +2,22 @@ pub const BUILD_SERVER: &'static str = "automaton";
 pub const BUILD_DOMAIN: &'static str = "d2.com";
 pub const BUILD_SERVER_PORT: u32 = 5000;
 pub const BUILD_ROUTE: &'static str = "job/Plans/job/BuildDistributionPipeline/build";
+pub const BUILD_JOB: &'static str = "job/Plans/job/BuildDistributionPipeline";
 // template param 1 = package 2 = tag
 pub const BUILD_PACKAGE_ROUTE: &'static str = "job/Packages/job/{}/tags/job/{}/build";
-pub const PARAM_CNT: usize = 6;
+pub const PARAM_CNT: usize = 11;
 pub const OS_VAR: &'static str = "DD_OS";
 pub const USERNAME: &'static str = "automaton";
 pub const PASSWORD: &'static str = "automatonAdmin!";
 pub const SVN_ROOT: &'static str = "http://svnmaster/svn/software/";
 pub const GIT_ROOT: &'static str = "http://dd-git.d2.com"; 
-pub const DEFAULT_PLATFORM: &'static str = "cent7";
\ No newline at end of file
+pub const DEFAULT_PLATFORM: &'static str = "cent7";
+// environment variable holding the url of the packalaka service's http api. The
+// packalaka command is used unless it is set.
+pub const PACKALAKA_URL_VAR: &'static str = "PACKALAKA_URL";
+pub const PACKALAKA_TIMEOUT_SECS: u64 = 30;
+// the service is tried first, so give up on it quickly in favour of the command
+pub const PACKALAKA_HTTP_TIMEOUT_SECS: u64 = 5;
+// how long to wait for the build server to discover a newly pushed tag
+pub const TAG_SCAN_TIMEOUT_SECS: u64 = 300;
+pub const TAG_SCAN_POLL_SECS: u64 = 5;
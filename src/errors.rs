@@ -10,6 +10,14 @@ pub enum RemoteBuildError {
     ConversionError(String),
     #[error("serde_json build error: {0:?}")]
     SerdeJsonError(#[from] serde_json::Error),
+    #[error("serde_yaml error: {0}")]
+    SerdeYamlError(#[from] serde_yaml::Error),
+    #[error("ConfigError: {0}")]
+    ConfigError(String),
+    #[error("RequestError: {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("MissingArgument: {0}")]
+    MissingArgument(String),
     #[error("NoneError")]
     NoneError,
     #[error("ShellFnError {0}")]
@@ -18,12 +26,75 @@ pub enum RemoteBuildError {
     EmptyError(String),
     #[error("ParseError: {0}")]
     ParseError(#[from] url::ParseError),
+    #[error("PatternError: {0}")]
+    PatternError(String),
     #[error("FlavorError: {0}")]
     FlavorError(String),
     #[error("FailureError: {0}")]
     FailureError(String),
     #[error("Gpi Record Failure {0}")]
     GpiRecordFailure(String),
+    #[error("Unknown package '{0}'.{}", crate::suggest::did_you_mean(.1))]
+    UnknownPackage(String, Vec<String>),
+    #[error("Unknown tag '{tag}' for package '{package}'.{}", crate::suggest::did_you_mean(.suggestions))]
+    UnknownTag { package: String, tag: String, suggestions: Vec<String> },
+    #[error("Package '{0}' has been retired. All of its sources are retired")]
+    RetiredPackage(String),
+    #[error("RepoRefError: {0}")]
+    RepoRefError(String),
+    #[error("Tag mismatch: requested {requested} but packalaka links to {linked}")]
+    TagMismatch { requested: String, linked: String },
+    #[error("Tag '{tag}' exists locally but not on {remote}. It must be pushed before it can be built")]
+    TagNotPushed { tag: String, remote: String },
+    #[error("Tag '{tag}' does not exist, either locally or on {remote}")]
+    MissingTag { tag: String, remote: String },
+    #[error("The working copy does not match the tag. {0}")]
+    WorkingCopyError(String),
+    #[error("VersionError: {0}")]
+    VersionError(String),
+    #[error("TagResolutionError: {0}")]
+    TagResolutionError(String),
+    #[error("RemoteUrlError: {0}")]
+    RemoteUrlError(String),
+    #[error("Nonstandard svn layout: {0}")]
+    SvnLayoutError(String),
+    #[error("VcsError: {0}")]
+    VcsError(String),
+    #[error("GitError: {0}")]
+    GitError(#[from] git2::Error),
+    #[error("SourceError: {0}")]
+    SourceError(String),
+    #[error("PackalakaError: {0}")]
+    PackalakaError(#[from] PackalakaError),
+}
+
+/// Errors encountered while communicating with packalaka
+#[derive(Error, Debug)]
+pub enum PackalakaError {
+    #[error("{0} did not respond within {1} seconds")]
+    Timeout(String, u64),
+    #[error("{0} is unavailable: {1}")]
+    Unavailable(String, String),
+    #[error("{0} returned status {1}")]
+    Status(String, u16),
+    #[error("`{command}` exited with {status}: {stderr}")]
+    Command {
+        command: String,
+        status: String,
+        stderr: String,
+    },
+    #[error("no packalaka data found at {0}")]
+    NotFound(String),
+    #[error("{0} has not been cached, and we are offline")]
+    NotCached(String),
+}
+
+impl PackalakaError {
+    /// Is the error due to packalaka being unreachable, as opposed to packalaka
+    /// responding with an error?
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self, PackalakaError::Timeout(..) | PackalakaError::Unavailable(..))
+    }
 }
 
 // make sure that we can convert from a reference to self
@@ -1,9 +1,9 @@
 //! Model Flavors, or are they Flavors?
-use crate::RemoteBuildError;
+use crate::{pattern::Pattern, RemoteBuildError};
 use failure::AsFail;
 use serde::{Deserialize, Serialize};
 use shellfn::shell;
-use std::{iter::Iterator, path::Path};
+use std::{fmt, iter::Iterator, path::Path};
 use log::error;
 
 
@@ -102,6 +102,219 @@ impl Flavors {
 }
 
 
+/// The name of the vanilla flavor
+pub const VANILLA: &str = "^";
+
+/// A flavor parsed into its components. Flavor names are composed of
+/// underscore separated components, where the host application is introduced
+/// by `for`. For example, `vray4.0.29259_for_maya2018` has a renderer component
+/// of `vray4.0.29259` and a host component of `for_maya2018`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct FlavorSpec {
+    name: String,
+    renderer: Option<String>,
+    host: Option<String>,
+}
+
+impl FlavorSpec {
+    /// Parse a flavor name (eg `vray4.0.29259_for_maya2018` or `^`) into a FlavorSpec
+    pub fn from_flavor(flavor: &str) -> Self {
+        let flavor = flavor.trim();
+        if flavor.is_empty() || flavor == VANILLA {
+            return Self::vanilla();
+        }
+        let mut renderer = Vec::new();
+        let mut host = None;
+        let mut components = flavor.split('_');
+        while let Some(component) = components.next() {
+            if component == "for" {
+                if let Some(app) = components.next() {
+                    host = Some(app.to_string());
+                    continue;
+                }
+            }
+            renderer.push(component);
+        }
+        Self {
+            name: flavor.to_string(),
+            renderer: if renderer.is_empty() { None } else { Some(renderer.join("_")) },
+            host,
+        }
+    }
+
+    /// Parse a FlavorSpec from a packalaka version string (eg `3.5.0_vray4.0.29259_for_maya2018`),
+    /// given the tag the version belongs to. A version which is identical to the tag is vanilla.
+    pub fn from_version(tag: &str, version: &str) -> Self {
+        Self::from_flavor(Self::flavor_name(tag, version))
+    }
+
+    /// Extract the flavor name from a packalaka version string, given the tag the version
+    /// belongs to.
+    pub fn flavor_name<'a>(tag: &str, version: &'a str) -> &'a str {
+        if version == tag {
+            return VANILLA;
+        }
+        version
+            .strip_prefix(tag)
+            .and_then(|rest| rest.strip_prefix('_'))
+            .unwrap_or(version)
+    }
+
+    /// New up the vanilla flavor
+    pub fn vanilla() -> Self {
+        Self {
+            name: VANILLA.to_string(),
+            renderer: None,
+            host: None,
+        }
+    }
+
+    /// Is this the vanilla flavor?
+    pub fn is_vanilla(&self) -> bool {
+        self.name == VANILLA
+    }
+
+    /// Retrieve the full flavor name, as it is passed to the build server
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Retrieve the renderer (or plugin) component, eg `vray4.0.29259`
+    pub fn renderer(&self) -> Option<&str> {
+        self.renderer.as_deref()
+    }
+
+    /// Retrieve the host application, eg `maya2018`
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Does the host application start with the supplied prefix?
+    pub fn is_for(&self, host: &str) -> bool {
+        self.host().map(|x| x.starts_with(host)).unwrap_or(false)
+    }
+
+    /// Does the renderer start with the supplied prefix?
+    pub fn is_with(&self, renderer: &str) -> bool {
+        self.renderer().map(|x| x.starts_with(renderer)).unwrap_or(false)
+    }
+}
+
+impl fmt::Display for FlavorSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Select flavors from the list of available flavors (as reported by
+/// `PackageTag::flavors()`) using include and exclude patterns.
+#[derive(Debug, Clone)]
+pub struct FlavorSelector {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    hosts: Vec<String>,
+    renderers: Vec<String>,
+}
+
+impl FlavorSelector {
+    /// New up a FlavorSelector given optional comma separated lists of include
+    /// and exclude patterns. See `Pattern` for the supported syntax.
+    pub fn new(include: Option<&str>, exclude: Option<&str>) -> Result<Self, RemoteBuildError> {
+        Ok(Self {
+            include: Pattern::parse_list(include.unwrap_or(""))?,
+            exclude: Pattern::parse_list(exclude.unwrap_or(""))?,
+            hosts: Vec::new(),
+            renderers: Vec::new(),
+        })
+    }
+
+    /// Restrict the selection to flavors whose host application starts with
+    /// one of the supplied, comma separated, prefixes (eg `maya2018`).
+    pub fn for_hosts(mut self, hosts: Option<&str>) -> Self {
+        self.hosts = split_components(hosts);
+        self
+    }
+
+    /// Restrict the selection to flavors whose renderer starts with one of the
+    /// supplied, comma separated, prefixes (eg `vray4`).
+    pub fn with_renderers(mut self, renderers: Option<&str>) -> Self {
+        self.renderers = split_components(renderers);
+        self
+    }
+
+    /// Select the flavors from `available` which match at least one include pattern
+    /// (or all of them when there are no include patterns) and none of the exclude
+    /// patterns. When host or renderer components have been supplied, the flavor must
+    /// also match one of each. The order of `available` is preserved.
+    ///
+    /// Any pattern or component which fails to match one of the available flavors results
+    /// in an error, as does a selection which ends up empty.
+    pub fn select<'a>(&self, available: &[&'a str]) -> Result<Vec<&'a str>, RemoteBuildError> {
+        let specs = available
+            .iter()
+            .map(|flav| FlavorSpec::from_flavor(flav))
+            .collect::<Vec<FlavorSpec>>();
+
+        let mut unmatched = self
+            .include
+            .iter()
+            .chain(self.exclude.iter())
+            .filter(|pattern| !available.iter().any(|flav| pattern.matches(flav)))
+            .map(|pattern| pattern.to_string())
+            .collect::<Vec<String>>();
+        unmatched.extend(
+            self.hosts
+                .iter()
+                .filter(|host| !specs.iter().any(|spec| spec.is_for(host)))
+                .map(|host| format!("for_{}", host)),
+        );
+        unmatched.extend(
+            self.renderers
+                .iter()
+                .filter(|renderer| !specs.iter().any(|spec| spec.is_with(renderer)))
+                .cloned(),
+        );
+
+        if !unmatched.is_empty() {
+            return Err(RemoteBuildError::FlavorError(format!(
+                "pattern(s) {} do not match any of the available flavors: {}",
+                unmatched.join(", "),
+                available.join(", ")
+            )));
+        }
+
+        let mut selected: Vec<&'a str> = Vec::with_capacity(available.len());
+        for (flav, spec) in available.iter().zip(specs.iter()) {
+            let included =
+                self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(flav));
+            let excluded = self.exclude.iter().any(|pattern| pattern.matches(flav));
+            let for_host = self.hosts.is_empty() || self.hosts.iter().any(|host| spec.is_for(host));
+            let with_renderer = self.renderers.is_empty()
+                || self.renderers.iter().any(|renderer| spec.is_with(renderer));
+            if included && !excluded && for_host && with_renderer && !selected.contains(flav) {
+                selected.push(flav);
+            }
+        }
+
+        if selected.is_empty() {
+            return Err(RemoteBuildError::FlavorError(
+                "all flavors have been excluded".into(),
+            ));
+        }
+        Ok(selected)
+    }
+}
+
+// split an optional comma separated list of flavor components
+fn split_components(components: Option<&str>) -> Vec<String> {
+    components
+        .unwrap_or("")
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
 
 #[shell]
 fn _get_flavors(flavor_path: &str) -> Result<impl Iterator<Item = String>, failure::Error> {
@@ -109,3 +322,95 @@ fn _get_flavors(flavor_path: &str) -> Result<impl Iterator<Item = String>, failu
     cd $FLAVOR_PATH && pk manifest --flavours --json=1
 "#
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AVAILABLE: &[&str] = &[
+        "^",
+        "vray3.6.27936_for_maya2018",
+        "vray4.0.29259_for_maya2018",
+        "vray4.0.29935_for_maya2016",
+    ];
+
+    #[test]
+    fn selects_everything_by_default() {
+        let selector = FlavorSelector::new(None, None).unwrap();
+        assert_eq!(selector.select(AVAILABLE).unwrap(), AVAILABLE.to_vec());
+    }
+
+    #[test]
+    fn can_select_with_glob_and_exclude() {
+        let selector = FlavorSelector::new(Some("vray4*"), Some("*maya2016")).unwrap();
+        assert_eq!(
+            selector.select(AVAILABLE).unwrap(),
+            vec!["vray4.0.29259_for_maya2018"]
+        );
+    }
+
+    #[test]
+    fn can_select_with_regex() {
+        let selector = FlavorSelector::new(Some("re:maya2018$,^"), None).unwrap();
+        assert_eq!(
+            selector.select(AVAILABLE).unwrap(),
+            vec!["^", "vray3.6.27936_for_maya2018", "vray4.0.29259_for_maya2018"]
+        );
+    }
+
+    #[test]
+    fn unmatched_pattern_is_an_error() {
+        let selector = FlavorSelector::new(Some("vray5*"), None).unwrap();
+        assert!(selector.select(AVAILABLE).is_err());
+        let selector = FlavorSelector::new(None, Some("arnold*")).unwrap();
+        assert!(selector.select(AVAILABLE).is_err());
+    }
+
+    #[test]
+    fn can_parse_flavor_spec() {
+        let spec = FlavorSpec::from_flavor("vray4.0.29259_for_maya2018");
+        assert_eq!(spec.renderer(), Some("vray4.0.29259"));
+        assert_eq!(spec.host(), Some("maya2018"));
+        assert!(spec.is_with("vray4"));
+        assert!(spec.is_for("maya"));
+        assert!(!spec.is_for("houdini"));
+
+        let spec = FlavorSpec::from_flavor("for_maya2018");
+        assert_eq!(spec.renderer(), None);
+        assert_eq!(spec.host(), Some("maya2018"));
+
+        let spec = FlavorSpec::from_flavor("^");
+        assert!(spec.is_vanilla());
+        assert_eq!(spec.host(), None);
+    }
+
+    #[test]
+    fn can_parse_flavor_spec_from_version() {
+        let spec = FlavorSpec::from_version("3.5.0_beta", "3.5.0_beta_vray4.0.29259_for_maya2018");
+        assert_eq!(spec.name(), "vray4.0.29259_for_maya2018");
+        assert_eq!(spec.renderer(), Some("vray4.0.29259"));
+        assert!(FlavorSpec::from_version("3.5.0", "3.5.0").is_vanilla());
+    }
+
+    #[test]
+    fn can_select_by_component() {
+        let selector = FlavorSelector::new(None, None)
+            .unwrap()
+            .for_hosts(Some("maya2018"))
+            .with_renderers(Some("vray4"));
+        assert_eq!(
+            selector.select(AVAILABLE).unwrap(),
+            vec!["vray4.0.29259_for_maya2018"]
+        );
+        let selector = FlavorSelector::new(None, None)
+            .unwrap()
+            .for_hosts(Some("houdini18"));
+        assert!(selector.select(AVAILABLE).is_err());
+    }
+
+    #[test]
+    fn excluding_everything_is_an_error() {
+        let selector = FlavorSelector::new(Some("vray4*"), Some("vray*")).unwrap();
+        assert!(selector.select(AVAILABLE).is_err());
+    }
+}
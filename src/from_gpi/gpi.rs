@@ -2,14 +2,15 @@ use std::collections::HashMap;
 use crate::vcs_system::VcsSystem;
 use strum_macros::{EnumString, Display, AsRefStr};
 use std::str::FromStr;
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 //use serde_json::Result;
 use crate::errors::RemoteBuildError;
-use shellfn::shell;
+use crate::from_gpi::packalaka_client::PackalakaClient;
+use crate::repo_ref::same_repo_for;
 use log::debug;
 
 /// A list of valid package types
-#[derive(EnumString, Display, Debug, AsRefStr, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Deserialize)]
+#[derive(EnumString, Display, Debug, AsRefStr, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
 pub enum PackageType {
     #[strum(serialize="port", serialize="Port")]
     #[serde(rename="port")]
@@ -39,7 +40,7 @@ impl std::convert::From<&str> for PackageType {
     }
 }
 
-#[derive(EnumString, Display, Debug, AsRefStr, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Deserialize)]
+#[derive(EnumString, Display, Debug, AsRefStr, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
 pub enum SourceStatus {
     #[strum(serialize="active", serialize="Active")]
     #[serde(rename="active")]
@@ -69,12 +70,14 @@ impl std::convert::From<&str> for SourceStatus {
 
 /// A Source represents a location for package source code within 
 /// a vcs system, of which there may be multiple ones.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
 pub struct Source {
     #[serde(rename = "initSubmodules")]
     init_submodules: bool,
     link: String,
     status: SourceStatus,
+    #[serde(default)]
+    subdirectory: String,
     tags: String,
     uses: VcsSystem,
 }
@@ -92,19 +95,67 @@ impl Source {
             init_submodules, 
             link: link.into(),
             status: status.into(),
+            subdirectory: String::new(),
             tags: tags.into(),
             uses: uses.into()
         }
     }
 
+    /// Set the subdirectory of the repository which the package lives in
+    pub fn with_subdirectory<S: Into<String>>(mut self, subdirectory: S) -> Self {
+        self.subdirectory = subdirectory.into();
+        self
+    }
+
     /// Determine whether the source is valid. An invalid source has an unknown status.
     pub fn is_valid(&self) -> bool {
         self.status.is_valid() && self.uses.is_valid()
     }
+
+    /// Retrieve the link to the source's repository
+    pub fn link(&self) -> &str {
+        &self.link
+    }
+
+    /// Retrieve the status of the source
+    pub fn status(&self) -> &SourceStatus {
+        &self.status
+    }
+
+    /// Retrieve the version control system the source lives in
+    pub fn uses(&self) -> &VcsSystem {
+        &self.uses
+    }
+
+    /// Retrieve the pattern identifying the tags which are drawn from the source
+    pub fn tags(&self) -> &str {
+        &self.tags
+    }
+
+    /// Should submodules be initialized when checking out the source
+    pub fn init_submodules(&self) -> bool {
+        self.init_submodules
+    }
+
+    /// Retrieve the subdirectory of the repository which the package lives in,
+    /// if the package does not live at the root of the repository
+    pub fn subdirectory(&self) -> Option<&str> {
+        let subdirectory = self.subdirectory.trim_matches('/');
+        if subdirectory.is_empty() {
+            None
+        } else {
+            Some(subdirectory)
+        }
+    }
+
+    /// Is the source active?
+    pub fn is_active(&self) -> bool {
+        self.status == SourceStatus::Active
+    }
 }
 
 /// A record has a type and a list of sources.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
 pub struct Record {
     sources: Vec<Source>,
     #[serde(rename="type")]
@@ -136,6 +187,34 @@ impl Record {
         }
     }
 
+    /// Retrieve the package type
+    pub fn pkg_type(&self) -> &PackageType {
+        &self.pkg_type
+    }
+
+    /// Retrieve the sources
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Retrieve the first active source, if one exists
+    pub fn active_source(&self) -> Option<&Source> {
+        self.sources.iter().find(|source| source.is_active())
+    }
+
+    /// Retrieve the source whose repository matches the supplied link. Any fragment
+    /// (eg #tag=1.2.3) is ignored when comparing links, and svn links are compared
+    /// by project (eg `<project>/tags/1.2.3` matches `<project>`).
+    pub fn source_for(&self, link: &str) -> Option<&Source> {
+        self.sources.iter().find(|source| same_repo_for(source.uses(), source.link(), link))
+    }
+
+    /// A record is retired if it has sources, all of which have been retired
+    pub fn is_retired(&self) -> bool {
+        !self.sources.is_empty() 
+            && self.sources.iter().all(|source| source.status == SourceStatus::Retired)
+    }
+
     /// test whether any of the components are Unknown. 
     pub fn is_valid(&self) -> bool {
         if !self.pkg_type.is_valid() {return false;}
@@ -160,7 +239,7 @@ impl<'a> std::ops::Index<usize> for Record {
 pub type GpiRecordsType = HashMap<String, Record>;
 
 /// root container for a set of one or more records.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GpiRecords {
     #[serde(flatten)]
     inner: GpiRecordsType
@@ -173,10 +252,9 @@ impl GpiRecords {
         serde_json::from_str(input).map_err(|x| RemoteBuildError::SerdeJsonError(x))
     }
     /// retrieve info from packalaka service
-    pub fn from_service(package: &str) -> Result<Self, RemoteBuildError> {
-        debug!("shelling out to get info");
-        let package_str = _get_packalaka(package).map_err(|e| RemoteBuildError::ShellFnError(format!("{:?}",e)))?;
-        Self::from_str(&package_str)
+    pub fn from_service(client: &dyn PackalakaClient, package: &str) -> Result<Self, RemoteBuildError> {
+        debug!("retrieving gpi record for {}", package);
+        client.query(package)
     }
 
     /// retrieve the number of packages
@@ -194,17 +272,17 @@ impl GpiRecords {
         self.inner.get(package)
     }
 
-}
-
+    /// Retrieve the names of the packages, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.inner.keys().map(|x| x.as_str()).collect::<Vec<&str>>();
+        names.sort();
+        names
+    }
 
-#[shell]
-fn _get_packalaka(package_name: &str) -> Result<String, shellfn::Error<std::convert::Infallible>> {
-    r#"
-        packalaka query --name $PACKAGE_NAME
-    "#
 }
 
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -330,4 +408,47 @@ r#"{
         let record = results.get("animtools");
         assert!(record.is_some());
     }
+
+    #[test]
+    fn can_identify_retired_record() {
+        let mut record = Record::new("source");
+        assert!(!record.is_retired());
+        record.add_source(Source::new(false, "bla", "retired", "bla", "svn"));
+        assert!(record.is_retired());
+        assert!(record.active_source().is_none());
+        record.add_source(Source::new(false, "foo", "active", "foo", "git"));
+        assert!(!record.is_retired());
+        assert_eq!(record.active_source().unwrap().link(), "foo");
+    }
+
+    #[test]
+    fn can_find_source_with_subdirectory() {
+        let mut record = Record::new("source");
+        record.add_source(Source::new(false, "http://dd-svn.d2.com/svn/animtools", "retired", "%", "svn"));
+        record.add_source(
+            Source::new(true, "ssh://git@dd-git.d2.com:2224/domains/monorepo.git", "active", "%", "git")
+                .with_subdirectory("/animtools/")
+        );
+        let source = record
+            .source_for("ssh://git@dd-git.d2.com:2224/domains/monorepo.git#tag=1.2.3")
+            .unwrap();
+        assert!(source.init_submodules());
+        assert_eq!(source.subdirectory(), Some("animtools"));
+        assert_eq!(record[0].subdirectory(), None);
+        assert!(record.source_for("http://dd-svn.d2.com/svn/other").is_none());
+    }
+
+    #[test]
+    fn can_find_svn_source_for_tag() {
+        let mut record = Record::new("source");
+        record.add_source(
+            Source::new(true, "http://dd-svn.d2.com/svn/software/packages/foo", "active", "%", "svn")
+                .with_subdirectory("src")
+        );
+        let source = record
+            .source_for("http://dd-svn.d2.com/svn/software/packages/foo/tags/1.2.3")
+            .unwrap();
+        assert_eq!(source.subdirectory(), Some("src"));
+        assert!(record.source_for("http://dd-svn.d2.com/svn/software/packages/bar/tags/1.2.3").is_none());
+    }
 }
@@ -1,3 +1,7 @@
 pub mod gpi;
 pub mod packalaka_tags;
+pub mod packalaka_client;
+pub mod packalaka_cache;
+pub mod source_selector;
+pub mod tag_spec;
 pub mod request;
\ No newline at end of file
@@ -0,0 +1,375 @@
+//! packalaka_cache.rs
+//!
+//! An on-disk cache of the json returned by packalaka. `CachingPackalakaClient`
+//! wraps another `PackalakaClient`, so cached responses are parsed by the same
+//! `GpiRecords` and `PackageTagList` parsers as live ones. Responses are stored
+//! under the cache directory, mirroring the layout used by `FilePackalakaClient`:
+//!
+//! * `query/<package>.json`
+//! * `query.json`
+//! * `tags/<package>/<tag>.json`
+//! * `tags/<package>.json`
+//!
+//! Tag responses which include pre-releases are stored under `tags-pre` rather than `tags`.
+use crate::{
+    config::CacheConfig,
+    errors::{PackalakaError, RemoteBuildError},
+    from_gpi::packalaka_client::PackalakaClient,
+    gpi::GpiRecords,
+    packalaka_tags::PackageTagList,
+};
+use log::{debug, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Name of the cache within the user's cache directory
+pub const CACHE_DIR: &str = "pkg-build-remote/packalaka";
+
+// The entries the cache owns within its root. The root may be configured to be
+// a directory shared with other files, so nothing else is ever removed.
+const ENTRIES: &[&str] = &["query", "query.json", "tags", "tags-pre"];
+
+/// How the cache should be consulted
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CacheMode {
+    /// Use cached responses which are younger than the ttl, and refresh the rest
+    Normal,
+    /// Ignore cached responses, replacing them with fresh ones
+    Refresh,
+    /// Never talk to packalaka. Cached responses are used regardless of their age
+    Offline,
+}
+
+impl CacheMode {
+    /// Determine the mode from the --refresh and --offline flags
+    pub fn from_flags(refresh: bool, offline: bool) -> Result<Self, RemoteBuildError> {
+        match (refresh, offline) {
+            (true, true) => Err(RemoteBuildError::ConfigError(
+                "--refresh and --offline may not be combined".into(),
+            )),
+            (true, false) => Ok(CacheMode::Refresh),
+            (false, true) => Ok(CacheMode::Offline),
+            (false, false) => Ok(CacheMode::Normal),
+        }
+    }
+}
+
+/// The on-disk store of packalaka responses
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PackalakaCache {
+    root: PathBuf,
+    ttl: Duration,
+}
+
+impl PackalakaCache {
+    /// New up a PackalakaCache rooted at the supplied directory
+    pub fn new<I: Into<PathBuf>>(root: I, ttl: Duration) -> Self {
+        Self {
+            root: root.into(),
+            ttl,
+        }
+    }
+
+    /// New up a PackalakaCache from the config, defaulting to the user's cache directory
+    pub fn from_config(config: &CacheConfig) -> Result<Self, RemoteBuildError> {
+        let root = match config.dir {
+            Some(ref dir) => dir.clone(),
+            None => dirs::cache_dir()
+                .map(|dir| dir.join(CACHE_DIR))
+                .ok_or_else(|| {
+                    RemoteBuildError::ConfigError("unable to determine the cache directory".into())
+                })?,
+        };
+        Ok(Self::new(root, Duration::from_secs(config.ttl_secs)))
+    }
+
+    /// Retrieve the root of the cache
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Remove all of the cached responses, leaving any other files in the root
+    /// alone. The root itself is only removed once it is empty.
+    pub fn clear(&self) -> Result<(), RemoteBuildError> {
+        for entry in ENTRIES {
+            let path = self.root.join(entry);
+            match fs::symlink_metadata(&path) {
+                Ok(meta) if meta.is_dir() => {
+                    debug!("removing {:?}", path);
+                    fs::remove_dir_all(&path)?;
+                }
+                Ok(_) => {
+                    debug!("removing {:?}", path);
+                    fs::remove_file(&path)?;
+                }
+                Err(_) => (),
+            }
+        }
+        let _ = fs::remove_dir(&self.root);
+        Ok(())
+    }
+
+    // the path to the entry identified by the supplied segments
+    fn path(&self, segments: &[&str]) -> PathBuf {
+        let mut path = self.root.clone();
+        if let Some((last, rest)) = segments.split_last() {
+            for segment in rest {
+                path.push(segment);
+            }
+            // tags contain dots, so we can't rely on set_extension
+            path.push(format!("{}.json", last));
+        }
+        path
+    }
+
+    // retrieve the entry, if it exists and, unless `any_age` is set, it is younger
+    // than the ttl.
+    fn get(&self, path: &Path, any_age: bool) -> Option<String> {
+        let fresh = any_age
+            || fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .map(|age| age < self.ttl)
+                .unwrap_or(false);
+        if !fresh {
+            return None;
+        }
+        fs::read_to_string(path).ok()
+    }
+
+    // store the entry, writing to a temporary file first so that a partially
+    // written entry is never read.
+    fn put(&self, path: &Path, json: &str) -> Result<(), RemoteBuildError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_file_name(format!(
+            ".{}.{}",
+            path.file_name().and_then(|x| x.to_str()).unwrap_or("entry"),
+            std::process::id()
+        ));
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// A PackalakaClient which consults the PackalakaCache before the client it wraps
+pub struct CachingPackalakaClient<C> {
+    inner: C,
+    cache: PackalakaCache,
+    mode: CacheMode,
+}
+
+impl<C: PackalakaClient> CachingPackalakaClient<C> {
+    /// New up a CachingPackalakaClient
+    pub fn new(inner: C, cache: PackalakaCache, mode: CacheMode) -> Self {
+        Self { inner, cache, mode }
+    }
+
+    // Retrieve the entry from the cache or, failing that, the inner client. Responses
+    // from the inner client are validated before they are cached. If the inner client
+    // is unavailable, a stale entry is better than nothing.
+    fn cached<F, V>(&self, segments: &[&str], fetch: F, validate: V) -> Result<String, RemoteBuildError>
+    where
+        F: Fn(&C) -> Result<String, RemoteBuildError>,
+        V: Fn(&str) -> Result<(), RemoteBuildError>,
+    {
+        let path = self.cache.path(segments);
+        match self.mode {
+            CacheMode::Offline => {
+                return self
+                    .cache
+                    .get(&path, true)
+                    .ok_or_else(|| PackalakaError::NotCached(path.display().to_string()).into());
+            }
+            CacheMode::Normal => {
+                if let Some(json) = self.cache.get(&path, false) {
+                    debug!("using cached {:?}", path);
+                    return Ok(json);
+                }
+            }
+            CacheMode::Refresh => (),
+        }
+        match fetch(&self.inner) {
+            Ok(json) => {
+                validate(&json)?;
+                if let Err(e) = self.cache.put(&path, &json) {
+                    warn!("unable to cache {:?}: {}", path, e);
+                }
+                Ok(json)
+            }
+            Err(RemoteBuildError::PackalakaError(ref e)) if e.is_unavailable() && self.mode == CacheMode::Normal => {
+                if let Some(json) = self.cache.get(&path, true) {
+                    warn!("{}. using stale cache entry {:?}", e, path);
+                    return Ok(json);
+                }
+                Err(PackalakaError::Unavailable(path.display().to_string(), e.to_string()).into())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// the directory of tag entries, which depends upon whether they include pre-releases
+fn tags_dir(include_pre: bool) -> &'static str {
+    if include_pre {
+        "tags-pre"
+    } else {
+        "tags"
+    }
+}
+
+fn validate_records(json: &str) -> Result<(), RemoteBuildError> {
+    GpiRecords::from_str(json).map(|_| ())
+}
+
+fn validate_tags(json: &str) -> Result<(), RemoteBuildError> {
+    PackageTagList::from_str(json).map(|_| ())
+}
+
+impl<C: PackalakaClient> PackalakaClient for CachingPackalakaClient<C> {
+    fn query_json(&self, package: &str) -> Result<String, RemoteBuildError> {
+        self.cached(&["query", package], |client| client.query_json(package), validate_records)
+    }
+
+    fn query_all_json(&self) -> Result<String, RemoteBuildError> {
+        self.cached(&["query"], |client| client.query_all_json(), validate_records)
+    }
+
+    fn tags_json(&self, package: &str, tag: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        self.cached(
+            &[tags_dir(include_pre), package, tag],
+            |client| client.tags_json(package, tag, include_pre),
+            validate_tags,
+        )
+    }
+
+    fn all_tags_json(&self, package: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        self.cached(
+            &[tags_dir(include_pre), package],
+            |client| client.all_tags_json(package, include_pre),
+            validate_tags,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packalaka_client::FilePackalakaClient, test_utils::TempDir};
+
+    const QUERY: &str = r#"{
+  "animtools": {
+    "sources": [
+      {"initSubmodules": false, "link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git",
+       "status": "active", "subdirectory": "", "tags": "%", "uses": "git"}
+    ],
+    "type": "source"
+  }
+}"#;
+
+    // a client serving QUERY for animtools, along with the cache
+    fn client(name: &str, ttl: u64, mode: CacheMode) -> (TempDir, CachingPackalakaClient<FilePackalakaClient>) {
+        let root = TempDir::new(&format!("cache-{}", name));
+        fs::create_dir_all(root.join("service").join("query")).unwrap();
+        fs::write(root.join("service").join("query").join("animtools.json"), QUERY).unwrap();
+        let cache = PackalakaCache::new(root.join("cache"), Duration::from_secs(ttl));
+        let client = CachingPackalakaClient::new(FilePackalakaClient::new(root.join("service")), cache, mode);
+        (root, client)
+    }
+
+    #[test]
+    fn can_serve_from_cache() {
+        let (root, client) = client("serve", 3600, CacheMode::Normal);
+        assert!(client.query("animtools").unwrap().has("animtools"));
+        assert!(root.join("cache").join("query").join("animtools.json").exists());
+
+        // the service no longer knows about animtools, but the cache does
+        fs::remove_file(root.join("service").join("query").join("animtools.json")).unwrap();
+        assert!(client.query("animtools").unwrap().has("animtools"));
+
+        // refreshing goes to the service
+        let client = CachingPackalakaClient::new(client.inner, client.cache, CacheMode::Refresh);
+        assert!(client.query("animtools").is_err());
+    }
+
+    #[test]
+    fn can_expire_entries() {
+        let (root, client) = client("expire", 0, CacheMode::Normal);
+        assert!(client.query("animtools").is_ok());
+        fs::remove_file(root.join("service").join("query").join("animtools.json")).unwrap();
+        // with a ttl of 0, every entry is stale
+        assert!(client.query("animtools").is_err());
+    }
+
+    #[test]
+    fn can_work_offline() {
+        let (_root, client) = client("offline", 0, CacheMode::Offline);
+        match client.query("animtools") {
+            Err(RemoteBuildError::PackalakaError(PackalakaError::NotCached(_))) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let client = CachingPackalakaClient::new(client.inner, client.cache, CacheMode::Normal);
+        assert!(client.query("animtools").is_ok());
+        // offline mode ignores the ttl
+        let client = CachingPackalakaClient::new(client.inner, client.cache, CacheMode::Offline);
+        assert!(client.query("animtools").is_ok());
+
+        client.cache.clear().unwrap();
+        assert!(!client.cache.root().exists());
+    }
+
+    #[test]
+    fn can_cache_pre_releases_separately() {
+        let (root, client) = client("pre", 3600, CacheMode::Normal);
+        let tags = root.join("service").join("tags").join("animtools");
+        fs::create_dir_all(&tags).unwrap();
+        let tag = |name: &str| {
+            format!(
+                r#"[{{"link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git#tag={0}", "name": "{0}", "status": "active", "uses": "git", "versions": []}}]"#,
+                name
+            )
+        };
+        fs::write(tags.join("3.5.0.json"), tag("3.5.0")).unwrap();
+        fs::write(tags.join("3.5.0.pre.json"), tag("3.5.0.pre")).unwrap();
+
+        assert!(client.tags_json("animtools", "3.5.0", true).unwrap().contains("tag=3.5.0\""));
+        assert!(root.join("cache").join("tags-pre").join("animtools").join("3.5.0.json").exists());
+        // a tag which happens to end in .pre is not mistaken for the pre-release entry
+        assert!(client.tags_json("animtools", "3.5.0.pre", false).unwrap().contains("tag=3.5.0.pre"));
+        assert!(root.join("cache").join("tags").join("animtools").join("3.5.0.pre.json").exists());
+    }
+
+    #[test]
+    fn clear_leaves_unrelated_files() {
+        let (root, client) = client("clear", 3600, CacheMode::Normal);
+        assert!(client.query("animtools").is_ok());
+        fs::write(root.join("cache").join("notes.txt"), "mine").unwrap();
+        fs::create_dir_all(root.join("cache").join("projects")).unwrap();
+
+        client.cache.clear().unwrap();
+        assert!(!root.join("cache").join("query").exists());
+        assert!(root.join("cache").join("notes.txt").exists());
+        assert!(root.join("cache").join("projects").exists());
+    }
+
+    #[test]
+    fn will_not_cache_invalid_responses() {
+        let (root, client) = client("invalid", 3600, CacheMode::Normal);
+        fs::write(root.join("service").join("query").join("animtools.json"), "{not json").unwrap();
+        assert!(client.query_json("animtools").is_err());
+        assert!(!root.join("cache").join("query").join("animtools.json").exists());
+    }
+
+    #[test]
+    fn refresh_and_offline_are_exclusive() {
+        assert_eq!(CacheMode::from_flags(false, false).unwrap(), CacheMode::Normal);
+        assert_eq!(CacheMode::from_flags(true, false).unwrap(), CacheMode::Refresh);
+        assert!(CacheMode::from_flags(true, true).is_err());
+    }
+}
@@ -0,0 +1,545 @@
+//! packalaka_client.rs
+//!
+//! Clients used to retrieve package information from packalaka. The
+//! `PackalakaClient` trait retrieves the raw json, which is then parsed
+//! by the same `GpiRecords` and `PackageTagList` parsers regardless of where
+//! the json came from. There are three implementations:
+//!
+//! * `CommandPackalakaClient` shells out to the `packalaka` command
+//! * `HttpPackalakaClient` talks to the packalaka service directly, and is only
+//!   used when the url of the service is supplied via `PACKALAKA_URL`
+//! * `FilePackalakaClient` reads json from disk, and is intended for tests
+//!
+//! `FallbackPackalakaClient` chains two clients together, falling back on the
+//! second when the first is unavailable. Finally, the clients may be wrapped in
+//! the `CachingPackalakaClient` (see packalaka_cache.rs).
+use crate::{
+    constants::{PACKALAKA_HTTP_TIMEOUT_SECS, PACKALAKA_TIMEOUT_SECS, PACKALAKA_URL_VAR},
+    config::CacheConfig,
+    errors::{PackalakaError, RemoteBuildError},
+    from_gpi::packalaka_cache::{CacheMode, CachingPackalakaClient, PackalakaCache},
+    gpi::GpiRecords,
+    packalaka_tags::PackageTagList,
+};
+use log::debug;
+use std::{
+    env,
+    io::Read,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Retrieve package information from packalaka
+pub trait PackalakaClient {
+    /// Retrieve the json describing the package's gpi record, as returned by
+    /// `packalaka query --name <package>`
+    fn query_json(&self, package: &str) -> Result<String, RemoteBuildError>;
+
+    /// Retrieve the json describing the gpi records of every package, as returned by
+    /// `packalaka query`
+    fn query_all_json(&self) -> Result<String, RemoteBuildError>;
+
+    /// Retrieve the json describing the package's tag, as returned by
+    /// `packalaka tags --json [--skip-pre] <package> <tag>`. Pre-releases are
+    /// skipped unless `include_pre` is true.
+    fn tags_json(&self, package: &str, tag: &str, include_pre: bool) -> Result<String, RemoteBuildError>;
+
+    /// Retrieve the json describing all of the package's tags, as returned by
+    /// `packalaka tags --json [--skip-pre] <package>`
+    fn all_tags_json(&self, package: &str, include_pre: bool) -> Result<String, RemoteBuildError>;
+
+    /// Retrieve the GpiRecords for the package
+    fn query(&self, package: &str) -> Result<GpiRecords, RemoteBuildError> {
+        GpiRecords::from_str(&self.query_json(package)?)
+    }
+
+    /// Retrieve the GpiRecords for every package
+    fn query_all(&self) -> Result<GpiRecords, RemoteBuildError> {
+        GpiRecords::from_str(&self.query_all_json()?)
+    }
+
+    /// Retrieve the PackageTagList for the package and tag
+    fn tags(&self, package: &str, tag: &str, include_pre: bool) -> Result<PackageTagList, RemoteBuildError> {
+        PackageTagList::from_str(&self.tags_json(package, tag, include_pre)?)
+    }
+
+    /// Retrieve the PackageTagList for all of the package's tags
+    fn all_tags(&self, package: &str, include_pre: bool) -> Result<PackageTagList, RemoteBuildError> {
+        PackageTagList::from_str(&self.all_tags_json(package, include_pre)?)
+    }
+}
+
+/// Construct the default client, which runs the packalaka command. If
+/// `PACKALAKA_URL` is set, the service is tried first, falling back on the
+/// command if the service is unavailable.
+pub fn default_client() -> Result<Box<dyn PackalakaClient>, RemoteBuildError> {
+    live_client()
+}
+
+/// Construct the default client, wrapped in a cache of packalaka responses which
+/// is consulted according to the supplied mode.
+pub fn cached_client(mode: CacheMode, config: &CacheConfig) -> Result<Box<dyn PackalakaClient>, RemoteBuildError> {
+    Ok(Box::new(CachingPackalakaClient::new(
+        live_client()?,
+        PackalakaCache::from_config(config)?,
+        mode,
+    )))
+}
+
+// the clients which talk to packalaka, as opposed to a cache or fixtures
+fn live_client() -> Result<Box<dyn PackalakaClient>, RemoteBuildError> {
+    let command = CommandPackalakaClient::new(Duration::from_secs(PACKALAKA_TIMEOUT_SECS));
+    match HttpPackalakaClient::from_env(Duration::from_secs(PACKALAKA_HTTP_TIMEOUT_SECS))? {
+        Some(http) => Ok(Box::new(FallbackPackalakaClient::new(http, command))),
+        None => Ok(Box::new(command)),
+    }
+}
+
+/// Retrieve package information from the packalaka service over http
+pub struct HttpPackalakaClient {
+    base_url: Url,
+    timeout: Duration,
+    client: reqwest::blocking::Client,
+}
+
+impl HttpPackalakaClient {
+    /// New up an HttpPackalakaClient given the base url of the service api
+    pub fn new(base_url: Url, timeout: Duration) -> Result<Self, RemoteBuildError> {
+        let client = reqwest::blocking::Client::builder().timeout(timeout).build()?;
+        Ok(Self {
+            base_url,
+            timeout,
+            client,
+        })
+    }
+
+    /// New up an HttpPackalakaClient using the url in the `PACKALAKA_URL`
+    /// environment variable. None is returned if it isn't set.
+    pub fn from_env(timeout: Duration) -> Result<Option<Self>, RemoteBuildError> {
+        match env::var(PACKALAKA_URL_VAR) {
+            Ok(url) if !url.trim().is_empty() => Ok(Some(Self::new(Url::parse(url.trim())?, timeout)?)),
+            _ => Ok(None),
+        }
+    }
+
+    // build a url from the base url and the supplied path segments
+    fn url(&self, segments: &[&str]) -> Result<Url, RemoteBuildError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| {
+                RemoteBuildError::ConversionError(format!("invalid packalaka url {}", self.base_url))
+            })?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    fn get(&self, url: Url) -> Result<String, RemoteBuildError> {
+        debug!("GET {}", url);
+        let response = self.client.get(url.clone()).send().map_err(|e| {
+            if e.is_timeout() {
+                PackalakaError::Timeout(url.to_string(), self.timeout.as_secs())
+            } else {
+                PackalakaError::Unavailable(url.to_string(), e.to_string())
+            }
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(status_error(url.as_str(), status).into());
+        }
+        Ok(response
+            .text()
+            .map_err(|e| PackalakaError::Unavailable(url.to_string(), e.to_string()))?)
+    }
+}
+
+// convert an unsuccessful response into an error. We can't tell a missing package
+// from a missing endpoint, so a 404 is treated as the service being unavailable,
+// leaving it to the packalaka command to decide.
+fn status_error(url: &str, status: reqwest::StatusCode) -> PackalakaError {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        PackalakaError::Unavailable(url.to_string(), "not found".to_string())
+    } else {
+        PackalakaError::Status(url.to_string(), status.as_u16())
+    }
+}
+
+impl PackalakaClient for HttpPackalakaClient {
+    fn query_json(&self, package: &str) -> Result<String, RemoteBuildError> {
+        let mut url = self.url(&["query"])?;
+        url.query_pairs_mut().append_pair("name", package);
+        self.get(url)
+    }
+
+    fn query_all_json(&self) -> Result<String, RemoteBuildError> {
+        self.get(self.url(&["query"])?)
+    }
+
+    fn tags_json(&self, package: &str, tag: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        let mut url = self.url(&["tags", package, tag])?;
+        if !include_pre {
+            url.query_pairs_mut().append_pair("skip_pre", "true");
+        }
+        self.get(url)
+    }
+
+    fn all_tags_json(&self, package: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        let mut url = self.url(&["tags", package])?;
+        if !include_pre {
+            url.query_pairs_mut().append_pair("skip_pre", "true");
+        }
+        self.get(url)
+    }
+}
+
+impl<C: PackalakaClient + ?Sized> PackalakaClient for Box<C> {
+    fn query_json(&self, package: &str) -> Result<String, RemoteBuildError> {
+        (**self).query_json(package)
+    }
+
+    fn query_all_json(&self) -> Result<String, RemoteBuildError> {
+        (**self).query_all_json()
+    }
+
+    fn tags_json(&self, package: &str, tag: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        (**self).tags_json(package, tag, include_pre)
+    }
+
+    fn all_tags_json(&self, package: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        (**self).all_tags_json(package, include_pre)
+    }
+}
+
+/// Retrieve package information by running the packalaka command
+pub struct CommandPackalakaClient {
+    program: String,
+    timeout: Duration,
+}
+
+impl CommandPackalakaClient {
+    /// New up a CommandPackalakaClient which runs `packalaka`
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_program("packalaka", timeout)
+    }
+
+    /// New up a CommandPackalakaClient which runs the supplied program in
+    /// place of `packalaka`
+    pub fn with_program<I: Into<String>>(program: I, timeout: Duration) -> Self {
+        Self {
+            program: program.into(),
+            timeout,
+        }
+    }
+
+    // run the program with the supplied args, returning stdout, and killing the
+    // process if it fails to complete within the timeout.
+    fn run(&self, args: &[&str]) -> Result<String, RemoteBuildError> {
+        let command = format!("{} {}", self.program, args.join(" "));
+        debug!("running {}", command);
+        let mut child = Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| PackalakaError::Unavailable(command.clone(), e.to_string()))?;
+
+        // read the pipes on separate threads so that the child can't block on a full pipe
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(PackalakaError::Timeout(command, self.timeout.as_secs()).into());
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        let stdout = stdout.join().unwrap_or_default();
+        if !status.success() {
+            return Err(PackalakaError::Command {
+                command,
+                status: status.to_string(),
+                stderr: stderr.join().unwrap_or_default().trim().to_string(),
+            }
+            .into());
+        }
+        Ok(stdout)
+    }
+}
+
+// read a child process' pipe to a string on a separate thread
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut output);
+        }
+        output
+    })
+}
+
+impl PackalakaClient for CommandPackalakaClient {
+    fn query_json(&self, package: &str) -> Result<String, RemoteBuildError> {
+        self.run(&["query", "--name", package])
+    }
+
+    fn query_all_json(&self) -> Result<String, RemoteBuildError> {
+        self.run(&["query"])
+    }
+
+    fn tags_json(&self, package: &str, tag: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        if include_pre {
+            self.run(&["tags", "--json", package, tag])
+        } else {
+            self.run(&["tags", "--json", "--skip-pre", package, tag])
+        }
+    }
+
+    fn all_tags_json(&self, package: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        if include_pre {
+            self.run(&["tags", "--json", package])
+        } else {
+            self.run(&["tags", "--json", "--skip-pre", package])
+        }
+    }
+}
+
+/// Retrieve package information from json files on disk. Records are stored
+/// in `<root>/query/<package>.json`, the records of every package in `<root>/query.json`,
+/// tags in `<root>/tags/<package>/<tag>.json`, and the full list of a package's tags in
+/// `<root>/tags/<package>.json`. Pre-releases are not filtered out.
+pub struct FilePackalakaClient {
+    root: PathBuf,
+}
+
+impl FilePackalakaClient {
+    /// New up a FilePackalakaClient rooted at the supplied directory
+    pub fn new<I: Into<PathBuf>>(root: I) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn read(&self, segments: &[&str]) -> Result<String, RemoteBuildError> {
+        // tags contain dots, so we can't rely on set_extension
+        let mut path = self.root.clone();
+        if let Some((last, rest)) = segments.split_last() {
+            for segment in rest {
+                path.push(segment);
+            }
+            path.push(format!("{}.json", last));
+        }
+        debug!("reading {:?}", path);
+        if !path.exists() {
+            return Err(PackalakaError::NotFound(path.display().to_string()).into());
+        }
+        Ok(std::fs::read_to_string(path)?)
+    }
+}
+
+impl PackalakaClient for FilePackalakaClient {
+    fn query_json(&self, package: &str) -> Result<String, RemoteBuildError> {
+        self.read(&["query", package])
+    }
+
+    fn query_all_json(&self) -> Result<String, RemoteBuildError> {
+        self.read(&["query"])
+    }
+
+    fn tags_json(&self, package: &str, tag: &str, _include_pre: bool) -> Result<String, RemoteBuildError> {
+        self.read(&["tags", package, tag])
+    }
+
+    fn all_tags_json(&self, package: &str, _include_pre: bool) -> Result<String, RemoteBuildError> {
+        self.read(&["tags", package])
+    }
+}
+
+/// Use the primary client, falling back on the secondary client when the primary
+/// client is unavailable (eg the service is down or times out).
+pub struct FallbackPackalakaClient<P, S> {
+    primary: P,
+    secondary: S,
+}
+
+impl<P: PackalakaClient, S: PackalakaClient> FallbackPackalakaClient<P, S> {
+    /// New up a FallbackPackalakaClient
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self { primary, secondary }
+    }
+
+    fn with_fallback<F>(&self, func: F) -> Result<String, RemoteBuildError>
+    where
+        F: Fn(&dyn PackalakaClient) -> Result<String, RemoteBuildError>,
+    {
+        match func(&self.primary) {
+            Err(RemoteBuildError::PackalakaError(ref e)) if e.is_unavailable() => {
+                debug!("{}. falling back", e);
+                func(&self.secondary)
+            }
+            result => result,
+        }
+    }
+}
+
+impl<P: PackalakaClient, S: PackalakaClient> PackalakaClient for FallbackPackalakaClient<P, S> {
+    fn query_json(&self, package: &str) -> Result<String, RemoteBuildError> {
+        self.with_fallback(|client| client.query_json(package))
+    }
+
+    fn query_all_json(&self) -> Result<String, RemoteBuildError> {
+        self.with_fallback(|client| client.query_all_json())
+    }
+
+    fn tags_json(&self, package: &str, tag: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        self.with_fallback(|client| client.tags_json(package, tag, include_pre))
+    }
+
+    fn all_tags_json(&self, package: &str, include_pre: bool) -> Result<String, RemoteBuildError> {
+        self.with_fallback(|client| client.all_tags_json(package, include_pre))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::fs;
+
+    const QUERY: &str = r#"{
+  "animtools": {
+    "sources": [
+      {
+        "initSubmodules": false,
+        "link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git",
+        "status": "active",
+        "subdirectory": "",
+        "tags": "%",
+        "uses": "git"
+      }
+    ],
+    "type": "source"
+  }
+}"#;
+
+    const TAGS: &str = r#"[
+  {
+    "link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git#tag=1.2.3",
+    "name": "1.2.3",
+    "status": "active",
+    "uses": "git",
+    "versions": ["1.2.3"]
+  }
+]"#;
+
+    // create a directory of packalaka fixtures, unique to the test
+    fn fixtures(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+        fs::create_dir_all(root.join("query")).unwrap();
+        fs::create_dir_all(root.join("tags").join("animtools")).unwrap();
+        fs::write(root.join("query").join("animtools.json"), QUERY).unwrap();
+        fs::write(root.join("query.json"), QUERY).unwrap();
+        fs::write(root.join("tags").join("animtools").join("1.2.3.json"), TAGS).unwrap();
+        fs::write(root.join("tags").join("animtools.json"), TAGS).unwrap();
+        root
+    }
+
+    // a client which is always unavailable
+    struct Unavailable;
+
+    impl PackalakaClient for Unavailable {
+        fn query_json(&self, package: &str) -> Result<String, RemoteBuildError> {
+            Err(PackalakaError::Unavailable(package.into(), "down".into()).into())
+        }
+        fn query_all_json(&self) -> Result<String, RemoteBuildError> {
+            Err(PackalakaError::Unavailable("query".into(), "down".into()).into())
+        }
+        fn tags_json(&self, package: &str, _tag: &str, _include_pre: bool) -> Result<String, RemoteBuildError> {
+            Err(PackalakaError::Timeout(package.into(), 1).into())
+        }
+        fn all_tags_json(&self, package: &str, _include_pre: bool) -> Result<String, RemoteBuildError> {
+            Err(PackalakaError::Timeout(package.into(), 1).into())
+        }
+    }
+
+    #[test]
+    fn can_read_from_files() {
+        let root = fixtures("files");
+        let client = FilePackalakaClient::new(root.path());
+        assert!(client.query("animtools").unwrap().has("animtools"));
+        assert_eq!(client.query_all().unwrap().names(), vec!["animtools"]);
+        assert_eq!(client.all_tags("animtools", false).unwrap().len(), 1);
+        assert_eq!(client.tags("animtools", "1.2.3", false).unwrap().len(), 1);
+        match client.query("animtoolz") {
+            Err(RemoteBuildError::PackalakaError(PackalakaError::NotFound(_))) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_fall_back_when_unavailable() {
+        let root = fixtures("fallback");
+        let client = FallbackPackalakaClient::new(Unavailable, FilePackalakaClient::new(root.path()));
+        assert!(client.query("animtools").unwrap().has("animtools"));
+        assert_eq!(client.tags("animtools", "1.2.3", false).unwrap().len(), 1);
+
+        // errors other than unavailability are not retried
+        let client = FallbackPackalakaClient::new(FilePackalakaClient::new(root.path()), Unavailable);
+        assert!(client.query("animtoolz").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn can_run_command() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = fixtures("command");
+        let script = root.join("packalaka");
+        fs::write(
+            &script,
+            format!("#!/bin/sh\nif [ \"$1\" = query ]; then cat {}; else exit 3; fi\n",
+                root.join("query").join("animtools.json").display()),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let client = CommandPackalakaClient::with_program(
+            script.to_str().unwrap(),
+            Duration::from_secs(10),
+        );
+        assert!(client.query("animtools").unwrap().has("animtools"));
+        match client.tags_json("animtools", "1.2.3", false) {
+            Err(RemoteBuildError::PackalakaError(PackalakaError::Command { .. })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_endpoint_is_unavailable() {
+        let url = "http://packalaka.d2.com/api/query";
+        assert!(status_error(url, reqwest::StatusCode::NOT_FOUND).is_unavailable());
+        match status_error(url, reqwest::StatusCode::INTERNAL_SERVER_ERROR) {
+            PackalakaError::Status(_, 500) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_command_is_unavailable() {
+        let client = CommandPackalakaClient::with_program(
+            "pkg-build-remote-no-such-packalaka",
+            Duration::from_secs(1),
+        );
+        match client.query_json("animtools") {
+            Err(RemoteBuildError::PackalakaError(ref e)) if e.is_unavailable() => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
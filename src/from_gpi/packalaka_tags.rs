@@ -1,15 +1,17 @@
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use crate::errors::RemoteBuildError;
-use shellfn::shell;
+use crate::from_gpi::packalaka_client::PackalakaClient;
 use log::debug;
 use crate::gpi::SourceStatus;
 use url::Url;
 use crate::vcs_system::VcsSystem;
-use std::str::FromStr;
+use crate::flavor::FlavorSpec;
+use crate::repo_ref::RepoRef;
+use crate::remote_url::parse_remote;
 
 /// packalaka tags --json <name> <tag> returns
 /// a list of these
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
 pub struct PackageTag {
     pub link:String,
     pub name: String,
@@ -26,21 +28,35 @@ impl PackageTag {
 
     /// Retrieve a list of flavors for a given Packalaka version
     pub fn flavors(&self) -> Vec<&str> {
-        let splitter = format!("{}_", &self.name);
-        self.versions.iter().map(move |x| {
-            let r=x.split(&splitter).last().unwrap_or("^");
-            if r == self.name {
-                "^"
-            } else {
-                r
-            } 
-
-        }).collect::<Vec<&str>>()
+        self.versions
+            .iter()
+            .map(|x| FlavorSpec::flavor_name(&self.name, x))
+            .collect::<Vec<&str>>()
+    }
+
+    /// Retrieve the list of flavors for a given Packalaka version, parsed
+    /// into their components.
+    pub fn flavor_specs(&self) -> Vec<FlavorSpec> {
+        self.versions
+            .iter()
+            .map(|x| FlavorSpec::from_version(&self.name, x))
+            .collect::<Vec<FlavorSpec>>()
+    }
+
+    /// Is the source the tag is drawn from active?
+    pub fn is_active(&self) -> bool {
+        self.status == SourceStatus::Active
+    }
+
+    /// Retrieve the repository and ref the tag is drawn from, verifying that the
+    /// link refers to the supplied tag.
+    pub fn repo_ref(&self, tag: &str) -> Result<RepoRef, RemoteBuildError> {
+        RepoRef::for_tag(&self.link, tag)
     }
 
     /// Retrieve the url
     pub fn link(&self) -> Result<Url, RemoteBuildError> {
-        Ok(Url::from_str(&self.link)?)
+        parse_remote(&self.link)
     }
 }
 
@@ -58,10 +74,9 @@ impl PackageTagList {
         Ok(PackageTagList{inner:lst})
     }
     /// retrieve info from packalaka service
-    pub fn from_service(package: &str, tag: &str) -> Result<Self, RemoteBuildError> {
-        debug!("shelling out to packalaka tags --json {} {}", package, tag);
-        let package_str = _get_packalaka(package, tag).map_err(|e| RemoteBuildError::ShellFnError(format!("{:?}",e)))?;
-        Self::from_str(&package_str)
+    pub fn from_service(client: &dyn PackalakaClient, package: &str, tag: &str, include_pre: bool) -> Result<Self, RemoteBuildError> {
+        debug!("retrieving packalaka tags {} {}", package, tag);
+        client.tags(package, tag, include_pre)
     }
 
     /// retrieve the number of PackageTags in the verison list
@@ -69,6 +84,11 @@ impl PackageTagList {
         self.inner.len()
     }
 
+    /// Iterate over the PackageTags
+    pub fn iter(&self) -> std::slice::Iter<'_, PackageTag> {
+        self.inner.iter()
+    }
+
     /// Retrieve a PackageTag by index.
     pub fn get(&self, idx: usize) -> Option<&PackageTag> {
         if self.inner.len() > idx {
@@ -81,13 +101,6 @@ impl PackageTagList {
 }
 
 
-#[shell]
-fn _get_packalaka(package_name: &str, package_tag: &str) -> Result<String, shellfn::Error<std::convert::Infallible>> {
-    r#"
-        packalaka tags --json --skip-pre $PACKAGE_NAME $PACKAGE_TAG
-    "#
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,4 +161,30 @@ r#"[
         let results = PackageTagList::from_str(data);
         assert!(results.is_ok());
     }
+
+    #[test]
+    fn can_retrieve_flavors() {
+        let data =
+r#"
+{
+    "link": "ssh://git@dd-git.d2.com:2224/domains/lighting/deferredpipeline.git#tag=3.5.0",
+    "name": "3.5.0",
+    "status": "active",
+    "uses": "git",
+    "versions": [
+      "3.5.0",
+      "3.5.0_vray3.6.27936_for_maya2018",
+      "3.5.0_vray4.0.29259_for_maya2018"
+    ]
+  }
+"#;
+        let tag = PackageTag::from_str(data).unwrap();
+        assert_eq!(
+            tag.flavors(), 
+            vec!["^", "vray3.6.27936_for_maya2018", "vray4.0.29259_for_maya2018"]
+        );
+        let specs = tag.flavor_specs();
+        assert_eq!(specs[2].renderer(), Some("vray4.0.29259"));
+        assert_eq!(specs[2].host(), Some("maya2018"));
+    }
 }
@@ -2,71 +2,339 @@
 use log::{debug,info};
 use crate::{
     BuildServer, 
+    FlavorSelector,
     RemoteBuildError,
+    errors::PackalakaError,
+    gpi::{GpiRecords, Record},
     packalaka_tags::PackageTagList, 
-    utils::request_build_for,
-    utils::request_package_build_for,
+    packalaka_client::{self, PackalakaClient},
+    packalaka_cache::CacheMode,
+    source_selector::SourceSelector,
+    tag_spec::resolve_tags,
+    suggest::suggest,
+    version::Version,
+    remote_url::UrlNormalizer,
+    utils::{build_plan_for, pick, request_builds_for, SourcedPlan},
+    utils::request_package_builds_for,
     cli::Opt,
+    config::Config,
     constants::DEFAULT_PLATFORM
 };
 
 
+// Look up the package in the gpi, refusing packages which are unknown (offering
+// suggestions where possible).
+pub fn find_record(client: &dyn PackalakaClient, name: &str) -> Result<Record, RemoteBuildError> {
+    let records = match GpiRecords::from_service(client, name) {
+        Ok(records) => Some(records),
+        Err(RemoteBuildError::PackalakaError(PackalakaError::NotFound(_))) => None,
+        Err(e) => return Err(e),
+    };
+    let record = match records.as_ref().and_then(|records| records.get(name)) {
+        Some(record) => record.clone(),
+        None => {
+            // failing to retrieve suggestions shouldn't mask the real problem
+            let suggestions = client
+                .query_all()
+                .map(|all| suggest(name, all.names()))
+                .unwrap_or_default();
+            return Err(RemoteBuildError::UnknownPackage(name.to_string(), suggestions));
+        }
+    };
+    debug!("Record {:#?}", record);
+    Ok(record)
+}
+
+// Look up the package in the gpi, refusing packages which are unknown (offering
+// suggestions where possible), or whose sources have all been retired.
+pub fn lookup_package(client: &dyn PackalakaClient, name: &str) -> Result<Record, RemoteBuildError> {
+    let record = find_record(client, name)?;
+    if record.is_retired() {
+        return Err(RemoteBuildError::RetiredPackage(name.to_string()));
+    }
+    Ok(record)
+}
+
+// Retrieve the PackageTagList for each of the tags. The full list of the package's
+// tags is only retrieved when a tag comes up empty, in order to explain why:
+// either the tag is a pre-release which packalaka has skipped, or the tag is
+// unknown, in which case it is reported along with suggestions. When prompting,
+// an unknown tag may be corrected by picking a suggestion.
+pub fn tag_lists_for(
+    client: &dyn PackalakaClient,
+    name: &str,
+    tags: &mut [String],
+    include_pre: bool,
+    prompt: bool,
+) -> Result<Vec<PackageTagList>, RemoteBuildError> {
+    let mut tag_lists = Vec::with_capacity(tags.len());
+    for tag in tags.iter_mut() {
+        loop {
+            let tag_list = match PackageTagList::from_service(client, name, tag, include_pre) {
+                Ok(tag_list) => Some(tag_list).filter(|x| x.len() > 0),
+                Err(RemoteBuildError::PackalakaError(PackalakaError::NotFound(_))) => None,
+                Err(e) => return Err(e),
+            };
+            if let Some(tag_list) = tag_list {
+                debug!("PackageTag {:#?}", tag_list);
+                tag_lists.push(tag_list);
+                break;
+            }
+            match missing_tag(client, name, tag, include_pre) {
+                e @ RemoteBuildError::UnknownTag { .. } if prompt => *tag = pick_suggestion(e)?,
+                e => return Err(e),
+            }
+        }
+    }
+    Ok(tag_lists)
+}
+
+// Explain why packalaka reported no records for the tag. Pre-releases are always
+// considered, so that a pre-release which has been excluded is not reported as unknown.
+fn missing_tag(client: &dyn PackalakaClient, name: &str, tag: &str, include_pre: bool) -> RemoteBuildError {
+    // failing to retrieve suggestions shouldn't mask the real problem
+    let known = match client.all_tags(name, true) {
+        Ok(known) => known.iter().map(|x| x.name.clone()).collect::<Vec<_>>(),
+        Err(e) => {
+            debug!("unable to retrieve the tags of {}: {}", name, e);
+            Vec::new()
+        }
+    };
+    if known.iter().any(|x| x == tag) {
+        // packalaka skips pre-releases unless asked not to
+        return match Version::parse(tag) {
+            Ok(ref version) if version.is_pre() && !include_pre => RemoteBuildError::EmptyError(format!(
+                "No Records exist for {}-{}. Use --include-pre to build pre-releases",
+                name, tag
+            )),
+            _ => RemoteBuildError::EmptyError(format!("No active records exist for {}-{}", name, tag)),
+        };
+    }
+    RemoteBuildError::UnknownTag {
+        package: name.to_string(),
+        tag: tag.to_string(),
+        suggestions: suggest(tag, known.iter().map(|x| x.as_str())),
+    }
+}
+
+// Offer the suggestions attached to an unknown package or tag as a pick, returning
+// the pick, or the original error when there is nothing to pick (or nothing is picked).
+fn pick_suggestion(error: RemoteBuildError) -> Result<String, RemoteBuildError> {
+    let picked = match error {
+        RemoteBuildError::UnknownPackage(ref name, ref suggestions) => {
+            println!("Unknown package '{}'", name);
+            pick("Build which package?", suggestions)
+        }
+        RemoteBuildError::UnknownTag { ref tag, ref suggestions, .. } => {
+            println!("Unknown tag '{}'", tag);
+            pick("Build which tag?", suggestions)
+        }
+        _ => None,
+    };
+    picked.ok_or(error)
+}
+
 // set up and execute the build using information gleaned from the gpi
 pub fn do_gpi(opts: Opt) ->  Result<(), RemoteBuildError> {
     
    
 
+    let (name, tag_args) = opts.package_and_tags()?;
     let build_server = BuildServer::default();
+    let config = Config::load()?;
+    let cache_mode = CacheMode::from_flags(opts.refresh, opts.offline)?;
+    let client = packalaka_client::cached_client(cache_mode, &config.cache)?;
 
-   
+    info!("validating {} against the gpi", name);
+    // when prompting, misspellings may be corrected by picking a suggestion
+    let (name, record) = match lookup_package(client.as_ref(), name) {
+        Ok(record) => (name.to_string(), record),
+        Err(e) if opts.prompt => {
+            let name = pick_suggestion(e)?;
+            let record = lookup_package(client.as_ref(), &name)?;
+            (name, record)
+        }
+        Err(e) => return Err(e),
+    };
+    let name = name.as_str();
+
+    let mut tags = resolve_tags(client.as_ref(), name, tag_args, opts.include_pre, opts.all_matching)?;
+    if tags.as_slice() != tag_args {
+        println!("Resolved {} {} to {}", name, tag_args.join(" "), tags.join(" "));
+    }
     
-    if opts.flavours.is_none() && opts.flavors.is_none() && opts.platforms.is_none() {
+    if opts.flavours.is_none() 
+        && opts.flavors.is_none() 
+        && opts.exclude_flavors.is_none() 
+        && opts.for_hosts.is_none()
+        && opts.with_renderers.is_none()
+        && !opts.missing_only
+        && opts.platforms.is_none() 
+        && opts.source.is_none()
+        && opts.prefer_vcs.is_none()
+    {
         info!("using package build route");
-        request_package_build_for(
+        // exact tags are left to the build server, unless the user is on hand to
+        // correct a misspelling. Pre-releases are considered, as the package build
+        // doesn't skip them.
+        if opts.prompt {
+            tag_lists_for(client.as_ref(), name, &mut tags, true, true)?;
+        }
+        request_package_builds_for(
             &build_server,
-            &opts.name,
-            &opts.tag,
+            name,
+            &tags,
+            &record,
             opts.dry_run,
             opts.verbose,
             opts.prompt
         )
     } else {
         info!("using per-tag build route");
-        let tags = PackageTagList::from_service(&opts.name, &opts.tag)?;
-        debug!("PackageTag {:#?}", tags);
+        // retrieve the tag lists up front, as the selected sources borrow from them.
+        // Looking up each tag also validates it
+        let tag_lists = tag_lists_for(client.as_ref(), name, &mut tags, opts.include_pre, opts.prompt)?;
 
-        if tags.len() == 0 {
-            return Err(RemoteBuildError::EmptyError(format!("No Records exist for {}-{}", &opts.name, &opts.tag)));
-        }
-        
-        let distribution = tags.get(0).unwrap(); // already testing that tags.len() > 0 above
-        
-        let platforms = opts.platforms.unwrap_or(DEFAULT_PLATFORM.to_string());
-        // if the user supplies flavors either via the flavor or flavour flag, go ahead and 
-        // use them. Otherwise, pull them from the gpi
-        let flavors = if opts.flavors.is_some() {
-            opts.flavors.unwrap()
-        } else if opts.flavours.is_some() {
-            opts.flavours.unwrap()
+        let platforms = opts.platforms.clone().unwrap_or(DEFAULT_PLATFORM.to_string());
+        // if the user supplies flavors either via the flavor or flavour flag, use them 
+        // to select from the flavors reported by the gpi. Otherwise, take all of them
+        let selector = FlavorSelector::new(
+            opts.flavors.as_deref().or(opts.flavours.as_deref()), 
+            opts.exclude_flavors.as_deref()
+        )?
+        .for_hosts(opts.for_hosts.as_deref())
+        .with_renderers(opts.with_renderers.as_deref());
+        let source_selector = SourceSelector::new(opts.source.as_deref())
+            .prefer_vcs(opts.prefer_vcs.as_deref())?;
+        let history = if opts.missing_only {
+            info!("retrieving build history in order to skip distributions which have been built");
+            Some(build_server.build_history()?)
         } else {
-            tags.get(0).unwrap().flavors().join(",")
+            None
         };
-        
-        debug!("platforms selected: {}", &platforms);
-        debug!("flavors selected:   {}", &flavors);
-        debug!("request_build_for(...)");
 
-        request_build_for(
+        let mut plans = Vec::with_capacity(tags.len());
+        for (tag, tag_list) in tags.iter().zip(tag_lists.iter()) {
+            let source = source_selector.select(tag_list)?;
+            info!("building {} from source {}", tag, source);
+            let flavors = selector.select(&source.tag.flavors())?.join(",");
+            
+            debug!("platforms selected: {}", &platforms);
+            debug!("flavors selected:   {}", &flavors);
+            let mut plan = build_plan_for(name, tag, &platforms, &flavors, &config.constraints)?;
+            if let Some(ref history) = history {
+                plan.skip_built(history);
+            }
+            plans.push(SourcedPlan { plan, source });
+        }
+
+        debug!("request_builds_for(...)");
+
+        request_builds_for(
             &build_server,
-            &opts.name,
-            &opts.tag,
-            &distribution.link()?,
-            &distribution.uses, //vcs
-            &platforms,
-            &flavors,
+            &plans,
+            &record,
+            &UrlNormalizer::from_config(&config.remotes)?,
             opts.dry_run,
             opts.verbose,
             opts.prompt,
         )
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packalaka_client::FilePackalakaClient, test_utils::TempDir};
+    use std::fs;
+
+    const QUERY: &str = r#"{
+  "animtools": {
+    "sources": [
+      {"initSubmodules": false, "link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git",
+       "status": "active", "subdirectory": "", "tags": "%", "uses": "git"}
+    ],
+    "type": "source"
+  },
+  "oldtools": {
+    "sources": [
+      {"initSubmodules": false, "link": "http://dd-svn.d2.com/svn/software/packages/oldtools",
+       "status": "retired", "subdirectory": "", "tags": "%", "uses": "svn"}
+    ],
+    "type": "source"
+  }
+}"#;
+
+    #[test]
+    fn can_validate_packages() {
+        let root = TempDir::new("lookup");
+        fs::create_dir_all(root.join("query")).unwrap();
+        fs::write(root.join("query").join("animtools.json"), QUERY).unwrap();
+        fs::write(root.join("query").join("oldtools.json"), QUERY).unwrap();
+        fs::write(root.join("query.json"), QUERY).unwrap();
+        let client = FilePackalakaClient::new(root.path());
+
+        assert!(lookup_package(&client, "animtools").is_ok());
+        match lookup_package(&client, "animtool") {
+            Err(RemoteBuildError::UnknownPackage(name, suggestions)) => {
+                assert_eq!(name, "animtool");
+                assert_eq!(suggestions, vec!["animtools".to_string()]);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        match lookup_package(&client, "oldtools") {
+            Err(RemoteBuildError::RetiredPackage(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_suggest_tags() {
+        let root = TempDir::new("check-tags");
+        fs::create_dir_all(root.join("tags").join("animtools")).unwrap();
+        let tag = |name: &str| {
+            format!(
+                r#"{{"link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git#tag={0}", "name": "{0}", "status": "active", "uses": "git", "versions": []}}"#,
+                name
+            )
+        };
+        fs::write(root.join("tags").join("animtools").join("3.5.1.json"), format!("[{}]", tag("3.5.1"))).unwrap();
+        let client = FilePackalakaClient::new(root.path());
+
+        // known tags are validated by their own lookup, without the full list
+        let mut tags = vec!["3.5.1".to_string()];
+        assert_eq!(tag_lists_for(&client, "animtools", &mut tags, false, false).unwrap().len(), 1);
+
+        fs::write(root.join("tags").join("animtools.json"), format!("[{}, {}]", tag("3.5.0"), tag("3.5.1"))).unwrap();
+        let mut tags = vec!["3.5.1".to_string(), "3.5.2".to_string()];
+        match tag_lists_for(&client, "animtools", &mut tags, false, false) {
+            Err(RemoteBuildError::UnknownTag { package, tag, suggestions }) => {
+                assert_eq!(package, "animtools");
+                assert_eq!(tag, "3.5.2");
+                assert_eq!(suggestions, vec!["3.5.0".to_string(), "3.5.1".to_string()]);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        // packalaka knows the tag, but has no active records for it
+        let mut tags = vec!["3.5.0".to_string()];
+        match tag_lists_for(&client, "animtools", &mut tags, false, false) {
+            Err(RemoteBuildError::EmptyError(msg)) => {
+                assert!(msg.contains("No active records"));
+                assert!(!msg.contains("--include-pre"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        // packalaka skipped the pre-release
+        fs::write(
+            root.join("tags").join("animtools.json"),
+            format!("[{}, {}, {}]", tag("3.5.0"), tag("3.5.1"), tag("3.6.0.alpha1")),
+        )
+        .unwrap();
+        let mut tags = vec!["3.6.0.alpha1".to_string()];
+        match tag_lists_for(&client, "animtools", &mut tags, false, false) {
+            Err(RemoteBuildError::EmptyError(msg)) => assert!(msg.contains("--include-pre")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
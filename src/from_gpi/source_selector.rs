@@ -0,0 +1,234 @@
+//! source_selector.rs
+//!
+//! A package may be drawn from more than one source (eg a package which moved from
+//! svn to git, or which has mirrors), in which case packalaka reports a PackageTag
+//! per source. The SourceSelector decides which of them to build from.
+use crate::{
+    errors::RemoteBuildError,
+    from_gpi::packalaka_tags::{PackageTag, PackageTagList},
+    repo_ref::{repo_of, same_repo, same_repo_for},
+    vcs_system::VcsSystem,
+};
+use log::debug;
+use std::fmt;
+
+/// The source explicitly requested by the user
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SourceChoice {
+    /// The index of the source, as reported by packalaka
+    Index(usize),
+    /// The url of the source's repository
+    Link(String),
+}
+
+impl<'a> From<&'a str> for SourceChoice {
+    fn from(value: &'a str) -> Self {
+        match value.parse::<usize>() {
+            Ok(idx) => SourceChoice::Index(idx),
+            Err(_) => SourceChoice::Link(value.to_string()),
+        }
+    }
+}
+
+/// The source chosen by the SourceSelector, along with its position amongst
+/// the sources reported by packalaka.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SelectedSource<'a> {
+    pub index: usize,
+    pub total: usize,
+    pub tag: &'a PackageTag,
+}
+
+impl<'a> fmt::Display for SelectedSource<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{} of {}] {} ({}, {})",
+            self.index,
+            self.total,
+            repo_of(&self.tag.link),
+            self.tag.uses.to_string(),
+            self.tag.status
+        )
+    }
+}
+
+/// Select a source from a PackageTagList. Absent an explicit choice, active sources
+/// are preferred over retired ones, and sources using the preferred VcsSystem (if
+/// supplied) are preferred over the rest. If more than one distinct source remains,
+/// the selection is ambiguous, and the user must choose one via --source.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SourceSelector {
+    choice: Option<SourceChoice>,
+    prefer_vcs: Option<VcsSystem>,
+}
+
+impl SourceSelector {
+    /// New up a SourceSelector, given an optional index or url identifying the source
+    pub fn new(source: Option<&str>) -> Self {
+        Self {
+            choice: source.map(SourceChoice::from),
+            prefer_vcs: None,
+        }
+    }
+
+    /// Prefer sources using the supplied VcsSystem
+    pub fn prefer_vcs(mut self, vcs: Option<&str>) -> Result<Self, RemoteBuildError> {
+        if let Some(vcs) = vcs {
+            let vcs = VcsSystem::from(vcs);
+            if !vcs.is_valid() {
+                return Err(RemoteBuildError::SourceError(format!(
+                    "Unknown vcs system: {}",
+                    vcs.to_string()
+                )));
+            }
+            self.prefer_vcs = Some(vcs);
+        }
+        Ok(self)
+    }
+
+    /// Select the source to build from
+    pub fn select<'a>(&self, tags: &'a PackageTagList) -> Result<SelectedSource<'a>, RemoteBuildError> {
+        let total = tags.len();
+        let all = (0..total).filter_map(|idx| tags.get(idx).map(|tag| (idx, tag)));
+        let selected = |(index, tag)| SelectedSource { index, total, tag };
+
+        if let Some(ref choice) = self.choice {
+            let found = match choice {
+                SourceChoice::Index(idx) => tags.get(*idx).map(|tag| (*idx, tag)),
+                SourceChoice::Link(link) => all
+                    .clone()
+                    .find(|(_, tag)| same_repo_for(&tag.uses, &tag.link, link)),
+            };
+            return found.map(selected).ok_or_else(|| {
+                RemoteBuildError::SourceError(format!(
+                    "Source '{}' not found. Choose one of:\n{}",
+                    match choice {
+                        SourceChoice::Index(idx) => idx.to_string(),
+                        SourceChoice::Link(link) => link.clone(),
+                    },
+                    describe(tags)
+                ))
+            });
+        }
+
+        let mut candidates = all.collect::<Vec<(usize, &PackageTag)>>();
+        if candidates.is_empty() {
+            return Err(RemoteBuildError::SourceError("No sources reported".into()));
+        }
+        if candidates.iter().any(|(_, tag)| tag.is_active()) {
+            candidates.retain(|(_, tag)| tag.is_active());
+        }
+        if let Some(ref vcs) = self.prefer_vcs {
+            if candidates.iter().any(|(_, tag)| tag.uses == *vcs) {
+                candidates.retain(|(_, tag)| tag.uses == *vcs);
+            } else {
+                debug!("no candidate sources use {}", vcs.to_string());
+            }
+        }
+        // the same repository may be reported more than once
+        let first = candidates[0];
+        if candidates.iter().all(|(_, tag)| same_repo(&tag.link, &first.1.link)) {
+            return Ok(selected(first));
+        }
+        Err(RemoteBuildError::SourceError(format!(
+            "Ambiguous source. Use --source to choose one of:\n{}",
+            describe(tags)
+        )))
+    }
+}
+
+// list the sources, one per line, along with their indices
+fn describe(tags: &PackageTagList) -> String {
+    (0..tags.len())
+        .filter_map(|idx| tags.get(idx).map(|tag| (idx, tag)))
+        .map(|(idx, tag)| {
+            format!(
+                "  {}: {} ({}, {})",
+                idx,
+                repo_of(&tag.link),
+                tag.uses.to_string(),
+                tag.status
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: &str = r#"[
+  {
+    "link": "http://dd-svn.d2.com/svn/software/packages/animtools/tags/1.2.3",
+    "name": "1.2.3",
+    "status": "retired",
+    "uses": "svn",
+    "versions": ["1.2.3"]
+  },
+  {
+    "link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git#tag=1.2.3",
+    "name": "1.2.3",
+    "status": "active",
+    "uses": "git",
+    "versions": ["1.2.3"]
+  },
+  {
+    "link": "ssh://git@dd-git.d2.com:2224/mirrors/animtools.git#tag=1.2.3",
+    "name": "1.2.3",
+    "status": "active",
+    "uses": "git",
+    "versions": ["1.2.3"]
+  }
+]"#;
+
+    #[test]
+    fn can_parse_source_choice() {
+        assert_eq!(SourceChoice::from("1"), SourceChoice::Index(1));
+        assert_eq!(
+            SourceChoice::from("http://foo/bar.git"),
+            SourceChoice::Link("http://foo/bar.git".into())
+        );
+    }
+
+    #[test]
+    fn can_select_explicit_source() {
+        let tags = PackageTagList::from_str(TAGS).unwrap();
+        let selected = SourceSelector::new(Some("0")).select(&tags).unwrap();
+        assert_eq!(selected.index, 0);
+        assert_eq!(selected.total, 3);
+
+        let selected = SourceSelector::new(Some("ssh://git@dd-git.d2.com:2224/mirrors/animtools.git"))
+            .select(&tags)
+            .unwrap();
+        assert_eq!(selected.index, 2);
+
+        assert!(SourceSelector::new(Some("3")).select(&tags).is_err());
+    }
+
+    #[test]
+    fn can_report_ambiguous_sources() {
+        let tags = PackageTagList::from_str(TAGS).unwrap();
+        match SourceSelector::new(None).select(&tags) {
+            Err(RemoteBuildError::SourceError(msg)) => assert!(msg.starts_with("Ambiguous")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_prefer_active_and_vcs() {
+        let tags = PackageTagList::from_str(&TAGS.replacen("mirrors", "domains/animation", 1)).unwrap();
+        // the duplicated git source is not ambiguous, and the retired svn source is skipped
+        let selected = SourceSelector::new(None).select(&tags).unwrap();
+        assert_eq!(selected.index, 1);
+        // a preference for svn does not override the preference for active sources
+        let selected = SourceSelector::new(None)
+            .prefer_vcs(Some("svn"))
+            .unwrap()
+            .select(&tags)
+            .unwrap();
+        assert_eq!(selected.index, 1);
+        assert!(SourceSelector::new(None).prefer_vcs(Some("cvs")).is_err());
+    }
+}
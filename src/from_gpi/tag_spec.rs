@@ -0,0 +1,210 @@
+//! tag_spec.rs
+//!
+//! The TAG supplied by the user may be an exact tag, or one of `latest`,
+//! `latest-stable`, or a version requirement (eg `^3.5`), which is resolved
+//! against the full list of the package's tags reported by packalaka.
+use crate::{
+    errors::RemoteBuildError,
+    from_gpi::{packalaka_client::PackalakaClient, packalaka_tags::PackageTagList},
+    version::{Version, VersionReq},
+};
+use log::debug;
+use std::fmt;
+
+/// The TAG, as supplied by the user
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TagSpec {
+    /// A specific tag
+    Exact(String),
+    /// The most recent tag. Pre-releases are only considered when requested
+    Latest,
+    /// The most recent tag which is not a pre-release
+    LatestStable,
+    /// The tags satisfying a version requirement
+    Range(VersionReq),
+}
+
+impl TagSpec {
+    /// Parse the tag supplied by the user
+    pub fn parse(input: &str) -> Result<Self, RemoteBuildError> {
+        match input.trim() {
+            "latest" => Ok(TagSpec::Latest),
+            "latest-stable" => Ok(TagSpec::LatestStable),
+            input if VersionReq::is_req(input) => Ok(TagSpec::Range(VersionReq::parse(input)?)),
+            input => Ok(TagSpec::Exact(input.to_string())),
+        }
+    }
+
+    /// Is the spec a specific tag, which does not need to be resolved?
+    pub fn is_exact(&self) -> bool {
+        matches!(self, TagSpec::Exact(_))
+    }
+
+    /// Retrieve the tags in the supplied list which satisfy the spec, in ascending
+    /// version order. Tags which do not parse as versions are ignored, unless they
+    /// are an exact match.
+    pub fn matching(&self, tags: &PackageTagList, include_pre: bool) -> Vec<String> {
+        if let TagSpec::Exact(tag) = self {
+            return tags
+                .iter()
+                .filter(|x| x.name == *tag)
+                .map(|x| x.name.clone())
+                .take(1)
+                .collect();
+        }
+        let mut versions = tags
+            .iter()
+            .filter_map(|x| Version::parse(&x.name).ok())
+            .filter(|version| match self {
+                TagSpec::Latest => include_pre || !version.is_pre(),
+                TagSpec::LatestStable => !version.is_pre(),
+                TagSpec::Range(req) => req.matches(version, include_pre),
+                TagSpec::Exact(_) => false,
+            })
+            .collect::<Vec<Version>>();
+        versions.sort();
+        // the same tag may be reported by more than one source
+        versions.dedup_by(|left, right| left.as_str() == right.as_str());
+        versions.into_iter().map(|x| x.as_str().to_string()).collect()
+    }
+
+    /// Resolve the spec to a single tag, namely the highest of the matching tags
+    pub fn resolve(&self, tags: &PackageTagList, include_pre: bool) -> Result<String, RemoteBuildError> {
+        if let TagSpec::Exact(tag) = self {
+            return Ok(tag.clone());
+        }
+        self.matching(tags, include_pre).pop().ok_or_else(|| {
+            RemoteBuildError::TagResolutionError(format!(
+                "no tags satisfy '{}'{}",
+                self,
+                if include_pre { "" } else { " (pre-releases excluded. see --include-pre)" }
+            ))
+        })
+    }
+}
+
+impl fmt::Display for TagSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagSpec::Exact(tag) => write!(f, "{}", tag),
+            TagSpec::Latest => write!(f, "latest"),
+            TagSpec::LatestStable => write!(f, "latest-stable"),
+            TagSpec::Range(req) => write!(f, "{}", req),
+        }
+    }
+}
+
+/// Resolve the TAGs supplied by the user for the package. Exact tags are returned
+/// as is, and the full list of tags is only retrieved from packalaka when one of
+/// the TAGs needs to be resolved. Each version requirement resolves to the highest
+/// matching tag, or to every matching tag when `all_matching` is true. The resulting
+/// tags are returned in the order supplied, without duplicates.
+pub fn resolve_tags(
+    client: &dyn PackalakaClient,
+    package: &str,
+    tags: &[String],
+    include_pre: bool,
+    all_matching: bool,
+) -> Result<Vec<String>, RemoteBuildError> {
+    let mut all_tags: Option<PackageTagList> = None;
+    let mut resolved = Vec::new();
+    for tag in tags {
+        let spec = TagSpec::parse(tag)?;
+        let matches = if spec.is_exact() {
+            vec![tag.clone()]
+        } else {
+            if all_tags.is_none() {
+                all_tags = Some(client.all_tags(package, include_pre)?);
+            }
+            let all_tags = all_tags.as_ref().unwrap();
+            if all_matching {
+                let matches = spec.matching(all_tags, include_pre);
+                if matches.is_empty() {
+                    // resolve reports the failure
+                    spec.resolve(all_tags, include_pre)?;
+                }
+                matches
+            } else {
+                vec![spec.resolve(all_tags, include_pre)?]
+            }
+        };
+        debug!("resolved {} {} to {:?}", package, spec, matches);
+        for tag in matches {
+            if !resolved.contains(&tag) {
+                resolved.push(tag);
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the json packalaka reports for the supplied tags
+    fn tags_json(names: &[&str]) -> String {
+        let entries = names
+            .iter()
+            .map(|name| {
+                format!(
+                    r#"{{"link": "http://dd-git.d2.com/foo.git#tag={0}", "name": "{0}", "status": "active", "uses": "git", "versions": ["{0}"]}}"#,
+                    name
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        format!("[{}]", entries)
+    }
+
+    fn tags(names: &[&str]) -> PackageTagList {
+        PackageTagList::from_str(&tags_json(names)).unwrap()
+    }
+
+    #[test]
+    fn can_parse_spec() {
+        assert_eq!(TagSpec::parse("latest").unwrap(), TagSpec::Latest);
+        assert_eq!(TagSpec::parse("latest-stable").unwrap(), TagSpec::LatestStable);
+        assert_eq!(TagSpec::parse("3.5.0").unwrap(), TagSpec::Exact("3.5.0".into()));
+        assert!(matches!(TagSpec::parse("^3.5").unwrap(), TagSpec::Range(_)));
+        assert!(TagSpec::parse("^three").is_err());
+    }
+
+    #[test]
+    fn can_resolve_spec() {
+        let tags = tags(&["3.4.2", "3.5.0", "3.5.1", "3.6.0.alpha1", "4.0.0", "experimental"]);
+        assert_eq!(TagSpec::Latest.resolve(&tags, false).unwrap(), "4.0.0");
+        let spec = TagSpec::parse("^3.5").unwrap();
+        assert_eq!(spec.resolve(&tags, false).unwrap(), "3.5.1");
+        assert_eq!(spec.resolve(&tags, true).unwrap(), "3.6.0.alpha1");
+        assert_eq!(spec.matching(&tags, false), vec!["3.5.0", "3.5.1"]);
+        assert!(TagSpec::parse("^5").unwrap().resolve(&tags, true).is_err());
+
+        let tags = self::tags(&["3.5.0", "3.6.0.alpha1"]);
+        assert_eq!(TagSpec::Latest.resolve(&tags, true).unwrap(), "3.6.0.alpha1");
+        assert_eq!(TagSpec::LatestStable.resolve(&tags, true).unwrap(), "3.5.0");
+    }
+
+    #[test]
+    fn can_resolve_multiple_tags() {
+        use crate::{packalaka_client::FilePackalakaClient, test_utils::TempDir};
+        let root = TempDir::new("tag-spec");
+        std::fs::create_dir_all(root.join("tags")).unwrap();
+        std::fs::write(
+            root.join("tags").join("foo.json"),
+            tags_json(&["3.4.0", "3.4.1", "3.4.2", "3.5.0"]),
+        )
+        .unwrap();
+        let client = FilePackalakaClient::new(root.path());
+
+        let args = vec!["3.4.*".to_string(), "3.4.2".to_string(), "latest".to_string()];
+        assert_eq!(resolve_tags(&client, "foo", &args, false, false).unwrap(), vec!["3.4.2", "3.5.0"]);
+        assert_eq!(
+            resolve_tags(&client, "foo", &args, false, true).unwrap(),
+            vec!["3.4.0", "3.4.1", "3.4.2", "3.5.0"]
+        );
+        // exact tags do not require packalaka
+        let args = vec!["9.9.9".to_string()];
+        assert_eq!(resolve_tags(&client, "bar", &args, false, true).unwrap(), vec!["9.9.9"]);
+    }
+}
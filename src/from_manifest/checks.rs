@@ -0,0 +1,288 @@
+//! checks.rs
+//!
+//! The build server checks the tag out of the remote repository, rather than
+//! building what is in the working copy. Before building from a working copy, we
+//! make sure that the two agree: that there are no uncommitted changes, that the
+//! working copy is at the tag, and that the manifest's version is the tag. Each
+//! check may be overridden, in which case a failure is reported but not fatal.
+use crate::{traits::Vcs, RemoteBuildError};
+use std::fmt;
+
+/// The outcome of a single check, along with a description of it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CheckStatus {
+    Passed(String),
+    /// The check could not be made
+    Skipped(String),
+    /// The check failed, but the user has overridden it
+    Overridden(String),
+    Failed(String),
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Passed(detail) => write!(f, "ok ({})", detail),
+            CheckStatus::Skipped(detail) => write!(f, "skipped ({})", detail),
+            CheckStatus::Overridden(detail) => write!(f, "overridden ({})", detail),
+            CheckStatus::Failed(detail) => write!(f, "FAILED ({})", detail),
+        }
+    }
+}
+
+/// A single check of the working copy
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    /// The flag which overrides a failure
+    pub flag: &'static str,
+    pub status: CheckStatus,
+}
+
+impl Check {
+    // a check which fails unless `passed`, or the failure is overridden
+    fn new(name: &'static str, flag: &'static str, overridden: bool, passed: bool, detail: String) -> Self {
+        let status = match (passed, overridden) {
+            (true, _) => CheckStatus::Passed(detail),
+            (false, true) => CheckStatus::Overridden(detail),
+            (false, false) => CheckStatus::Failed(detail),
+        };
+        Self { name, flag, status }
+    }
+
+    fn skipped(name: &'static str, flag: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            flag,
+            status: CheckStatus::Skipped(detail),
+        }
+    }
+
+    /// Has the check failed, without being overridden?
+    pub fn failed(&self) -> bool {
+        matches!(self.status, CheckStatus::Failed(_))
+    }
+}
+
+/// The checks which the user has chosen to override
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Overrides {
+    pub dirty: bool,
+    pub head: bool,
+    pub version: bool,
+}
+
+/// The results of checking a working copy against the tag being built
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct WorkingCopyChecks {
+    checks: Vec<Check>,
+}
+
+impl WorkingCopyChecks {
+    /// Check the working copy against the tag.
+    ///
+    /// # Parameters
+    ///
+    /// * `repo` - The working copy
+    /// * `tag` - The tag being built
+    /// * `manifest_version` - The version recorded in the working copy's manifest, if any
+    /// * `overrides` - The checks whose failure should not prevent the build
+    pub fn run(repo: &dyn Vcs, tag: &str, manifest_version: Option<&str>, overrides: Overrides) -> Self {
+        Self {
+            checks: vec![
+                dirty_check(repo, overrides),
+                head_check(repo, tag, overrides),
+                version_check(tag, manifest_version, overrides),
+            ],
+        }
+    }
+
+    /// Check the working copy before the tag has been created, in which case the
+    /// working copy can't be compared with it. The parameters are those of `run`.
+    pub fn before_tagging(repo: &dyn Vcs, tag: &str, manifest_version: Option<&str>, overrides: Overrides) -> Self {
+        Self {
+            checks: vec![
+                dirty_check(repo, overrides),
+                version_check(tag, manifest_version, overrides),
+            ],
+        }
+    }
+
+    /// Iterate over the checks
+    pub fn iter(&self) -> std::slice::Iter<'_, Check> {
+        self.checks.iter()
+    }
+
+    /// Report any checks which have failed, without being overridden, as an error
+    pub fn verify(&self) -> Result<(), RemoteBuildError> {
+        let failures = self
+            .checks
+            .iter()
+            .filter(|check| check.failed())
+            .map(|check| format!("{}: {} (override with {})", check.name, check.status, check.flag))
+            .collect::<Vec<_>>();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(RemoteBuildError::WorkingCopyError(failures.join("; ")))
+        }
+    }
+}
+
+// are there uncommitted changes?
+fn dirty_check(repo: &dyn Vcs, overrides: Overrides) -> Check {
+    match repo.is_dirty() {
+        Ok(false) => Check::new("Working Copy", "--allow-dirty", overrides.dirty, true, "clean".into()),
+        Ok(true) => Check::new(
+            "Working Copy",
+            "--allow-dirty",
+            overrides.dirty,
+            false,
+            "there are uncommitted changes".into(),
+        ),
+        Err(e) => Check::skipped("Working Copy", "--allow-dirty", e.to_string()),
+    }
+}
+
+// is the working copy at the tag?
+fn head_check(repo: &dyn Vcs, tag: &str, overrides: Overrides) -> Check {
+    match (repo.head_ref(), repo.tag_ref(tag)) {
+        (Ok(head), Ok(Some(tagged))) => Check::new(
+            "Working Copy Ref",
+            "--allow-head-mismatch",
+            overrides.head,
+            head == tagged,
+            if head == tagged {
+                format!("at {}", tag)
+            } else {
+                format!("at {}, but {} is {}", head, tag, tagged)
+            },
+        ),
+        (Ok(_), Ok(None)) => Check::skipped(
+            "Working Copy Ref",
+            "--allow-head-mismatch",
+            format!("{} can't be resolved in the working copy", tag),
+        ),
+        (Err(e), _) | (_, Err(e)) => Check::skipped("Working Copy Ref", "--allow-head-mismatch", e.to_string()),
+    }
+}
+
+// is the manifest's version the tag?
+fn version_check(tag: &str, manifest_version: Option<&str>, overrides: Overrides) -> Check {
+    match manifest_version {
+        Some(version) => Check::new(
+            "Manifest Version",
+            "--allow-version-mismatch",
+            overrides.version,
+            version == tag,
+            if version == tag {
+                version.to_string()
+            } else {
+                format!("the manifest is at {}, but the tag is {}", version, tag)
+            },
+        ),
+        None => Check::skipped(
+            "Manifest Version",
+            "--allow-version-mismatch",
+            "there is no manifest".into(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VcsSystem;
+    use std::path::Path;
+
+    struct Fixture {
+        dirty: bool,
+        head: &'static str,
+    }
+
+    impl Vcs for Fixture {
+        fn open(_path: &Path) -> Result<Self, RemoteBuildError> {
+            Ok(Fixture { dirty: false, head: "abc" })
+        }
+        fn system(&self) -> VcsSystem {
+            VcsSystem::Git
+        }
+        fn root(&self) -> &Path {
+            Path::new("/")
+        }
+        fn named_remotes(&self) -> Result<Vec<(String, url::Url)>, RemoteBuildError> {
+            Ok(Vec::new())
+        }
+        fn tags(&self) -> Result<Vec<String>, RemoteBuildError> {
+            Ok(vec!["1.0.0".to_string()])
+        }
+        fn head_ref(&self) -> Result<String, RemoteBuildError> {
+            Ok(self.head.to_string())
+        }
+        fn is_dirty(&self) -> Result<bool, RemoteBuildError> {
+            Ok(self.dirty)
+        }
+        fn tag_ref(&self, tag: &str) -> Result<Option<String>, RemoteBuildError> {
+            Ok(if tag == "1.0.0" { Some("abc".to_string()) } else { None })
+        }
+    }
+
+    #[test]
+    fn can_check_working_copy() {
+        let clean = Fixture { dirty: false, head: "abc" };
+        let checks = WorkingCopyChecks::run(&clean, "1.0.0", Some("1.0.0"), Overrides::default());
+        assert!(checks.iter().all(|x| matches!(x.status, CheckStatus::Passed(_))));
+        assert!(checks.verify().is_ok());
+
+        // an unknown tag can't be compared with HEAD, and there may be no manifest
+        let checks = WorkingCopyChecks::run(&clean, "1.0.1", None, Overrides::default());
+        assert!(checks.iter().skip(1).all(|x| matches!(x.status, CheckStatus::Skipped(_))));
+        assert!(checks.verify().is_ok());
+
+        let dirty = Fixture { dirty: true, head: "def" };
+        let checks = WorkingCopyChecks::run(&dirty, "1.0.0", Some("0.9.0"), Overrides::default());
+        assert!(checks.iter().all(|x| x.failed()));
+        match checks.verify() {
+            Err(RemoteBuildError::WorkingCopyError(msg)) => {
+                assert!(msg.contains("--allow-dirty"));
+                assert!(msg.contains("--allow-head-mismatch"));
+                assert!(msg.contains("--allow-version-mismatch"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_override_checks() {
+        let dirty = Fixture { dirty: true, head: "def" };
+        let overrides = Overrides {
+            dirty: true,
+            head: true,
+            version: false,
+        };
+        let checks = WorkingCopyChecks::run(&dirty, "1.0.0", Some("0.9.0"), overrides);
+        let statuses = checks.iter().map(|x| &x.status).collect::<Vec<_>>();
+        assert!(matches!(statuses[0], CheckStatus::Overridden(_)));
+        assert!(matches!(statuses[1], CheckStatus::Overridden(_)));
+        assert!(matches!(statuses[2], CheckStatus::Failed(_)));
+        match checks.verify() {
+            Err(RemoteBuildError::WorkingCopyError(msg)) => {
+                assert!(!msg.contains("--allow-dirty"));
+                assert!(msg.contains("--allow-version-mismatch"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_check_before_tagging() {
+        let dirty = Fixture { dirty: true, head: "def" };
+        let checks = WorkingCopyChecks::before_tagging(&dirty, "1.0.0", Some("1.0.0"), Overrides::default());
+        let names = checks.iter().map(|x| x.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Working Copy", "Manifest Version"]);
+        match checks.verify() {
+            Err(RemoteBuildError::WorkingCopyError(msg)) => assert!(!msg.contains("--allow-head-mismatch")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -29,17 +29,14 @@ impl Minifest {
         })?;
         let mut mmiter = _get_minifest_from_grep(path)?;
 
-        let mut attrs = Vec::with_capacity(2);
-        attrs.push(
+        let attrs = vec![
             mmiter
                 .next()
                 .ok_or_else(|| ShellFnError("Unable to get name from manifest. Perhaps the manifest was not found?".to_string()))?,
-        );
-        attrs.push(
             mmiter
                 .next()
                 .ok_or_else(|| ShellFnError("Unable to get version from manifest.".to_string()))?,
-        );
+        ];
         let mut name = String::new();
         let mut version = String::new();
 
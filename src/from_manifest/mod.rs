@@ -1,2 +1,3 @@
+pub mod checks;
 pub mod minifest;
 pub mod request;
\ No newline at end of file
@@ -1,43 +1,52 @@
-
 use log::{debug, error};
 use crate::{
-    traits::*, BuildServer, Git, Minifest, RemoteBuildError,
-    Svn, VcsSystem, utils, Flavors, utils::request_build_for,
-     cli::Opt
+    traits::*, BuildServer, Minifest, RemoteBuildError, RefKind, RepoRef, SvnLayout,
+    VcsSystem, utils, Flavors, utils::{build_plan_for, request_local_build_for, LocalSource},
+    from_manifest::checks::{Overrides, WorkingCopyChecks},
+    build_plan::BuildPlan,
+    remote_url::{parse_remote, UrlNormalizer},
+    cli::{LocalOpt, Opt},
+    config::Config,
+    constants::DEFAULT_PLATFORM,
+    gpi::GpiRecords,
+    packalaka_cache::CacheMode,
+    packalaka_client::{self, PackalakaClient},
+    repo_ref::same_repo_for,
+    svn_layout,
 };
-use std::env;
+use std::{env, path::Path};
+use url::Url;
 
 /// set up the build using local information gleaned from the manifest and the local vcs repo
-pub fn do_local( opts: Opt) ->  Result<(), RemoteBuildError> {
+pub fn do_local(opts: &Opt, local: &LocalOpt) ->  Result<(), RemoteBuildError> {
 
     debug!("retrieving project path");
-    let project_path = opts.project_path.unwrap_or(env::current_dir()?);
+    let project_path = match local.project_path {
+        Some(ref path) => path.clone(),
+        None => env::current_dir()?,
+    };
     debug!("project_path: {:?}", project_path);
 
     debug!("retrieving flavors");
-    let flavors = Flavors::resolve_flavors(opts.flavors, opts.flavours, Some(&project_path));
-   
-    if flavors.is_err() {
-        let e = flavors.unwrap_err();
-        error!("Unable to resolve flavors: {}.", e);
-        std::process::exit(1);
-    }
-
-    let flavors = flavors.unwrap();
+    let flavors = match Flavors::resolve_flavors(local.flavors.clone(), local.flavours.clone(), Some(&project_path)) {
+        Ok(flavors) => flavors,
+        Err(e) => {
+            error!("Unable to resolve flavors: {}.", e);
+            std::process::exit(1);
+        }
+    };
     debug!("flavors retrieved: {:?}", flavors);
 
     debug!("identifying vcs system");
-    let vcs = VcsSystem::identify_vcs(&opts.vcs, &project_path);
+    let vcs = VcsSystem::identify_vcs(&local.vcs, &project_path);
     debug!("VCS system {:?}", vcs);
 
     let build_server = BuildServer::default();
 
     debug!("retrieving name and version");
-    let (name, version) = if opts.name.is_some() && opts.tag.is_some() {
-        (opts.name.unwrap(), opts.tag.unwrap())
-    } else {
-        let minifest = utils::get_minifest(&project_path, &opts.name, &opts.tag); //Minifest::from_disk(Some(&project_path));
-        
+    let (name, version) = {
+        let minifest = utils::get_minifest(&project_path, &local.name, &local.tag);
+
         if  let Ok(Minifest{name, version}) = minifest {
             (name,version)
 
@@ -45,113 +54,365 @@ pub fn do_local( opts: Opt) ->  Result<(), RemoteBuildError> {
             let e = minifest.unwrap_err();
             error!("Problem with manifest. {}", e);
             std::process::exit(1);
-        }    
+        }
     };
 
     debug!("name: {:?} version: {:?}", name, version);
-   
+
+    let config = Config::load()?;
+    let platforms = local.platforms.as_deref().unwrap_or(DEFAULT_PLATFORM);
+    let plan = build_plan_for(&name, &version, platforms, &flavors, &config.constraints)?;
+
     build_from_vcs(
-        &project_path, 
-        &opts.vcs_url,
-        &flavors, 
-        &vcs, 
-        &opts.platforms,
-        &build_server, 
-        &name, 
-        &version,
-         opts.dry_run,
-        opts.verbose,
-        opts.prompt,
+        &build_server,
+        &project_path,
+        local,
+        &vcs,
+        &plan,
+        &config,
+        opts,
     )
 }
 
+/// Choose the remote to build from. The remote named by the user must exist.
+/// Otherwise, the first of the preferred remotes which exists is chosen, falling
+/// back on the first remote.
+///
+/// # Parameters
+///
+/// * `remotes` - The (name, url) pairs of the working copy's remotes
+/// * `requested` - The name of the remote requested by the user, if any
+/// * `preferred` - The names of the remotes to use, in order of preference
+///
+/// # Returns
+///
+/// The (name, url) of the chosen remote, or Error
+pub fn select_remote(
+    remotes: Vec<(String, Url)>,
+    requested: Option<&str>,
+    preferred: &[String],
+) -> Result<(String, Url), RemoteBuildError> {
+    let names = remotes.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ");
+    let position = match requested {
+        Some(requested) => Some(remotes.iter().position(|(name, _)| name == requested).ok_or_else(|| {
+            RemoteBuildError::VcsError(format!("there is no remote named '{}'. Remotes: {}", requested, names))
+        })?),
+        None => preferred
+            .iter()
+            .find_map(|preferred| remotes.iter().position(|(name, _)| name == preferred)),
+    };
+    remotes
+        .into_iter()
+        .nth(position.unwrap_or(0))
+        .ok_or_else(|| RemoteBuildError::VcsError("the working copy has no remotes".to_string()))
+}
+
+// Retrieve the url of the repository, unless the user has supplied one.
+fn vcs_project_url(repo: &dyn Vcs, local: &LocalOpt, config: &Config) -> Result<Url, RemoteBuildError> {
+    if let Some(ref vcs_url) = local.vcs_url {
+        debug!("parsing url");
+        return parse_remote(vcs_url);
+    }
+    debug!("vcs_url empty. Retrieving url from {:?}", repo.root());
+    let (name, url) = select_remote(repo.named_remotes()?, local.remote.as_deref(), &config.remotes.preferred)
+        .map_err(|e| {
+            RemoteBuildError::VcsError(format!(
+                "Unable to get {} server url from project path: {}",
+                repo.system().to_string(),
+                e
+            ))
+        })?;
+    debug!("using remote {} ({})", name, url);
+    Ok(url)
+}
+
+// construct the packalaka client, honoring the cache flags
+fn packalaka(config: &Config, opts: &Opt) -> Result<Box<dyn PackalakaClient>, RemoteBuildError> {
+    packalaka_client::cached_client(CacheMode::from_flags(opts.refresh, opts.offline)?, &config.cache)
+}
+
+// Warn if packalaka links the package to a different repository, which usually
+// means that we are about to build from a fork. Failing to reach packalaka, or a
+// package which packalaka doesn't know about, is not a problem.
+fn warn_unless_linked(name: &str, url: &Url, normalizer: &UrlNormalizer, config: &Config, opts: &Opt) {
+    let record = packalaka(config, opts).and_then(|client| GpiRecords::from_service(client.as_ref(), name));
+    let source = match record {
+        Ok(ref records) => records.get(name).and_then(|record| record.active_source()),
+        Err(ref e) => {
+            debug!("unable to retrieve the packalaka link for {}: {}", name, e);
+            None
+        }
+    };
+    let (mut link, uses) = match source.map(|source| (normalizer.normalize(source.link()), source.uses())) {
+        Some((Ok(link), uses)) => (link, uses),
+        Some((Err(e), _)) => {
+            debug!("unable to parse the packalaka link for {}: {}", name, e);
+            return;
+        }
+        None => return,
+    };
+    link.set_fragment(None);
+    if !same_repo_for(uses, link.as_str(), url.as_str()) {
+        println!("Warning: building {} from {}, but packalaka links it to {}", name, url, link);
+    }
+}
+
+/// Make sure that the tag has been pushed to the remote repository at `url`, so
+/// that the build server is able to check it out. When the version control
+/// system can't tell, the tags known to packalaka are consulted instead. A tag
+/// which exists locally but not on the remote is reported separately from one
+/// which doesn't exist at all. If neither the remote nor packalaka can be
+/// queried, a warning is printed, and the build goes ahead.
+pub fn verify_remote_tag(
+    repo: &dyn Vcs,
+    url: &Url,
+    name: &str,
+    tag: &str,
+    config: &Config,
+    opts: &Opt,
+) -> Result<(), RemoteBuildError> {
+    // mercurial records its tags in the working copy, so a tag which is missing
+    // from it doesn't exist anywhere, whatever packalaka has to say
+    if repo.system() == VcsSystem::Mercurial && !repo.tag_exists(tag)? {
+        return Err(RemoteBuildError::MissingTag {
+            tag: tag.to_string(),
+            remote: url.to_string(),
+        });
+    }
+    let on_remote = match repo.remote_tag_exists(url, tag) {
+        Ok(on_remote) => on_remote,
+        Err(e) => {
+            println!("Warning: unable to query {} for tag {}: {}", url, tag, e);
+            None
+        }
+    };
+    let on_remote = match on_remote {
+        Some(on_remote) => on_remote,
+        None => match packalaka(config, opts).and_then(|client| client.all_tags(name, true)) {
+            Ok(tags) => tags.iter().any(|x| x.name == tag),
+            Err(e) => {
+                println!("Warning: unable to verify that tag {} exists on {}: {}", tag, url, e);
+                return Ok(());
+            }
+        },
+    };
+    debug!("tag {} exists on {}: {}", tag, url, on_remote);
+    if on_remote {
+        return Ok(());
+    }
+    let remote = url.to_string();
+    let tag = tag.to_string();
+    if repo.tag_exists(&tag)? {
+        Err(RemoteBuildError::TagNotPushed { tag, remote })
+    } else {
+        Err(RemoteBuildError::MissingTag { tag, remote })
+    }
+}
 
+// Construct the RepoRef of the tag within the repository at `url`. Svn tags are
+// directories within the project, so the tag is built from `<project>/tags/<tag>`,
+// which is also how packalaka links them.
+fn repo_ref_for(vcs: &VcsSystem, url: &Url, tag: &str) -> Result<RepoRef, RemoteBuildError> {
+    match vcs {
+        VcsSystem::Svn => {
+            // a url supplied by the user may point anywhere within the project
+            let project = SvnLayout::from_url(url, None)
+                .map(|layout| layout.project)
+                .unwrap_or_else(|_| url.clone());
+            Ok(RepoRef::new(svn_layout::tag_url(&project, tag), RefKind::Tag, tag))
+        }
+        _ => RepoRef::for_tag(url.as_str(), tag),
+    }
+}
 
 /// execute a build using a particular vcs's information
 pub fn build_from_vcs(
-    project_path: &std::path::Path, 
-    vcs_url: &Option<String>,
-    flavors: &str, 
-    vcs: &VcsSystem, 
-    platforms: &str,
-    build_server: &BuildServer, 
-    name: &str, 
-    version: &str,
-    dry_run: bool,
-    verbose: bool,
-    prompt:bool,
+    build_server: &BuildServer,
+    project_path: &Path,
+    local: &LocalOpt,
+    vcs: &VcsSystem,
+    plan: &BuildPlan,
+    config: &Config,
+    opts: &Opt,
 ) ->  Result<(), RemoteBuildError>  {
 
-    debug!("invoking request_build_for based on vcs system");
-    match vcs {
-        VcsSystem::Svn => {
-            debug!("Svn system...");
-            //let vcs_project_url = Svn::get_url(&project_path, minifest.version.as_str())?;
-            let vcs_project_url = vcs_url.as_ref().map(|u|{
-                debug!("parsing Url");
-                url::Url::parse(&u).unwrap_or_else(|_e| {
-                    error!("unable to construct url from path provided");
-                    std::process::exit(1);
-                })
-            
-            }).unwrap_or_else(|| {
-                let url = Svn::get_server_urls(&project_path).unwrap_or_else(|_|{
-                    error!("Unable to get svn server url from project path");
-                    std::process::exit(1);
-                });
-                url[0].clone()
-            });
-
-            debug!("vcs_project_url: {:?}", &vcs_project_url);
-            debug!("calling request_build_for");
-            request_build_for(
-                &build_server,
-                &name,
-                &version,
-                &vcs_project_url,
-                &vcs,
-                &platforms,
-                &flavors,
-                dry_run,
-                verbose,
-                prompt,
-            )
-        }
-        VcsSystem::Git => {
-            debug!("Git system...");
-            let vcs_project_url = vcs_url.as_ref().map(|u|{
-                debug!("parsing url");
-                url::Url::parse(&u).unwrap_or_else(|e| {
-                    error!("unable to construct url from {}. error {}", u, e);
-                    std::process::exit(1);
-                })
-            
-            }).unwrap_or_else(|| {
-                debug!("vcs_url empty. Retrieving url from Git::get_server_urls({:?})", &project_path);
-                let url = Git::get_server_urls(&project_path).unwrap_or_else(|_|{
-                    error!("Unable to get git server url from project path");
-                    std::process::exit(1);
-                });
-                assert!(url.len() > 0);
-                url[0].clone()
-            });
-            debug!("vcs_project_url: {:?}", &vcs_project_url);
-            request_build_for(
-                &build_server,
-                &name,
-                &version,
-                &vcs_project_url,
-                &vcs,
-                &platforms,
-                &flavors,
-                dry_run,
-                verbose,
-                prompt,
-            )
-        }
-        _ => {
-            error!("SCM must either be svn or git");
-            std::process::exit(1);
+    debug!("invoking request_local_build_for based on vcs system");
+    let repo = vcs.open(project_path)?;
+    let normalizer = UrlNormalizer::from_config(&config.remotes)?;
+    // the tag is verified against the remote as we see it, which may not be
+    // reachable once it has been rewritten for the build server
+    let project_url = vcs_project_url(repo.as_ref(), local, config)?;
+    verify_remote_tag(repo.as_ref(), &project_url, &plan.project, &plan.version, config, opts)?;
+    let vcs_project_url = normalizer.rewrite(&project_url)?;
+    debug!("vcs_project_url: {:?}", &vcs_project_url);
+    warn_unless_linked(&plan.project, &vcs_project_url, &normalizer, config, opts);
+
+    // the manifest is read afresh, as the name and tag may have been supplied
+    let manifest_version = Minifest::from_disk(Some(project_path)).ok().map(|x| x.version);
+    let overrides = Overrides {
+        dirty: local.allow_dirty,
+        head: local.allow_head_mismatch,
+        version: local.allow_version_mismatch,
+    };
+    let source = LocalSource {
+        repo_ref: repo_ref_for(vcs, &vcs_project_url, &plan.version)?,
+        vcs,
+        checks: WorkingCopyChecks::run(repo.as_ref(), &plan.version, manifest_version.as_deref(), overrides),
+    };
+    request_local_build_for(
+        build_server,
+        plan,
+        &source,
+        opts.dry_run,
+        opts.verbose,
+        opts.prompt,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remotes(names: &[&str]) -> Vec<(String, Url)> {
+        names
+            .iter()
+            .map(|name| {
+                let url = Url::parse(&format!("ssh://git@dd-git.d2.com:2224/{}/animtools.git", name)).unwrap();
+                (name.to_string(), url)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn can_select_remote() {
+        let preferred = vec!["upstream".to_string(), "origin".to_string()];
+        let (name, _) = select_remote(remotes(&["fork", "origin", "upstream"]), None, &preferred).unwrap();
+        assert_eq!(name, "upstream");
+        let (name, _) = select_remote(remotes(&["fork", "origin"]), None, &preferred).unwrap();
+        assert_eq!(name, "origin");
+        let (name, url) = select_remote(remotes(&["fork", "mirror"]), None, &preferred).unwrap();
+        assert_eq!(name, "fork");
+        assert_eq!(url.as_str(), "ssh://git@dd-git.d2.com:2224/fork/animtools.git");
+        let (name, _) = select_remote(remotes(&["fork", "upstream"]), Some("fork"), &preferred).unwrap();
+        assert_eq!(name, "fork");
+        match select_remote(remotes(&["fork", "upstream"]), Some("origin"), &preferred) {
+            Err(RemoteBuildError::VcsError(msg)) => assert!(msg.contains("Remotes: fork, upstream")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(select_remote(Vec::new(), None, &preferred).is_err());
+    }
+
+    // a working copy with a single local tag, 1.0.0, of which only the remote's
+    // tags are known. The remote can't be queried if its tags are None.
+    struct Unpushed {
+        system: VcsSystem,
+        remote_tags: Option<Vec<&'static str>>,
+    }
+
+    impl Vcs for Unpushed {
+        fn open(_path: &Path) -> Result<Self, RemoteBuildError> {
+            Ok(Unpushed {
+                system: VcsSystem::Git,
+                remote_tags: Some(Vec::new()),
+            })
+        }
+        fn system(&self) -> VcsSystem {
+            self.system.clone()
+        }
+        fn root(&self) -> &Path {
+            Path::new("/")
+        }
+        fn named_remotes(&self) -> Result<Vec<(String, Url)>, RemoteBuildError> {
+            Ok(remotes(&["origin"]))
+        }
+        fn tags(&self) -> Result<Vec<String>, RemoteBuildError> {
+            Ok(vec!["1.0.0".to_string()])
+        }
+        fn head_ref(&self) -> Result<String, RemoteBuildError> {
+            Ok("abc123".to_string())
+        }
+        fn is_dirty(&self) -> Result<bool, RemoteBuildError> {
+            Ok(false)
+        }
+        fn remote_tag_exists(&self, _url: &Url, tag: &str) -> Result<Option<bool>, RemoteBuildError> {
+            match self.remote_tags {
+                Some(ref tags) => Ok(Some(tags.contains(&tag))),
+                None => Err(RemoteBuildError::VcsError("unable to authenticate".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn can_verify_remote_tag() {
+        use structopt::StructOpt;
+        let opts = Opt::from_iter(&["pkg-build-remote", "local"]);
+        let config = Config::default();
+        let (_, url) = remotes(&["origin"]).remove(0);
+        let repo = Unpushed {
+            system: VcsSystem::Git,
+            remote_tags: Some(vec!["0.9.0"]),
+        };
+        match verify_remote_tag(&repo, &url, "animtools", "1.0.0", &config, &opts) {
+            Err(RemoteBuildError::TagNotPushed { tag, .. }) => assert_eq!(tag, "1.0.0"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match verify_remote_tag(&repo, &url, "animtools", "1.0.1", &config, &opts) {
+            Err(RemoteBuildError::MissingTag { tag, .. }) => assert_eq!(tag, "1.0.1"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(verify_remote_tag(&repo, &url, "animtools", "0.9.0", &config, &opts).is_ok());
+    }
+
+    #[test]
+    fn unreachable_remotes_are_not_fatal() {
+        use structopt::StructOpt;
+        // nothing has been cached, so packalaka can't be consulted either
+        let opts = Opt::from_iter(&["pkg-build-remote", "--offline", "local"]);
+        let cache = crate::test_utils::TempDir::new("verify");
+        let config = Config {
+            cache: crate::config::CacheConfig {
+                dir: Some(cache.to_path_buf()),
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let (_, url) = remotes(&["origin"]).remove(0);
+        let repo = Unpushed {
+            system: VcsSystem::Git,
+            remote_tags: None,
+        };
+        assert!(verify_remote_tag(&repo, &url, "animtools", "1.0.0", &config, &opts).is_ok());
+
+        // mercurial knows its tags locally, whether or not the remote can be reached
+        let repo = Unpushed {
+            system: VcsSystem::Mercurial,
+            remote_tags: None,
+        };
+        assert!(verify_remote_tag(&repo, &url, "animtools", "1.0.0", &config, &opts).is_ok());
+        match verify_remote_tag(&repo, &url, "animtools", "1.0.1", &config, &opts) {
+            Err(RemoteBuildError::MissingTag { tag, .. }) => assert_eq!(tag, "1.0.1"),
+            other => panic!("unexpected {:?}", other),
         }
     }
+
+    #[test]
+    fn can_build_svn_tags_from_their_own_url() {
+        let tag_url = "http://svnmaster/svn/software/packages/foo/tags/1.2.0";
+        for url in &[
+            "http://svnmaster/svn/software/packages/foo",
+            "http://svnmaster/svn/software/packages/foo/trunk",
+            tag_url,
+        ] {
+            let repo_ref = repo_ref_for(&VcsSystem::Svn, &Url::parse(url).unwrap(), "1.2.0").unwrap();
+            let request = crate::BuildRequest::new("foo", "1.2.0", "^", repo_ref.to_string().as_str(), "svn", "cent7")
+                .unwrap();
+            assert_eq!(request.repo.as_str(), tag_url);
+            assert_eq!(request.ref_kind, RefKind::Tag);
+            assert_eq!(request.ref_name, "1.2.0");
+        }
+
+        let url = Url::parse("ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git").unwrap();
+        let repo_ref = repo_ref_for(&VcsSystem::Git, &url, "1.2.0").unwrap();
+        assert_eq!(repo_ref.url, url);
+    }
 }
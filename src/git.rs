@@ -1,111 +1,327 @@
 //! Introspect a local git repository
-use crate::prelude::*;
-use failure;
-use git2::Repository;
-use std::{env::current_dir, path::Path, path::PathBuf};
+use crate::{prelude::*, remote_url::parse_remote, RemoteBuildError, VcsSystem};
+use git2::{Cred, CredentialType, Direction, ErrorCode, PushOptions, RemoteCallbacks, Repository, StatusOptions};
+use log::debug;
+use std::path::Path;
 
-/// Query the remote urls for a git repo, which we assume to be in the current working directory.
-pub struct Git;
+/// A local git repository
+pub struct Git {
+    repo: Repository,
+}
 
 impl Vcs for Git {
-    /// Test to see if the current directory houses a git repo.
+    /// Open the git repository housing `path`, searching `path` and its parents.
+    /// Worktrees and submodules, whose `.git` is a file pointing elsewhere, are
+    /// opened as repositories in their own right. A repository is never created.
     ///
     /// # Parameters
     ///
-    /// None
+    /// * `path` - The path to a directory within a git working copy
     ///
     /// # Returns
     ///
-    /// Bool indicating whether the current working directory
-    /// houses a git repo or not.
-    fn is_cwd_repo() -> bool {
-        let cwd = current_dir().unwrap();
-        Git::is_repo(cwd)
+    /// Git or Error
+    fn open(path: &Path) -> Result<Self, RemoteBuildError> {
+        let repo = Repository::discover(path).map_err(|e| match e.code() {
+            ErrorCode::NotFound => RemoteBuildError::VcsError(format!(
+                "{} is not within a git repository", path.display()
+            )),
+            _ => e.into(),
+        })?;
+        if repo.is_bare() {
+            return Err(RemoteBuildError::VcsError(format!(
+                "{} is a bare git repository, which has no working copy", repo.path().display()
+            )));
+        }
+        debug!("discovered git repository {:?} (worktree: {}) from {:?}", repo.workdir(), repo.is_worktree(), path);
+        Ok(Git { repo })
     }
 
-    /// Test to see if the provied directory houses a git repo.
-    ///
-    /// # Parameters
-    ///
-    /// * `pathbuf` - The path to the directory which we wish to test.
-    ///
-    /// # Returns
-    ///
-    /// Bool indicating whether the supplied pathbuf houses a git repo or not
-    fn is_repo<I: Into<PathBuf>>(pathbuf: I) -> bool {
-        let mut pathbuf = pathbuf.into();
-        pathbuf.push(".git");
-        pathbuf.exists()
+    fn system(&self) -> VcsSystem {
+        VcsSystem::Git
     }
 
-    /// get remote repositories for the local git repo in `path`.
-    ///
-    /// # Parameters
-    ///
-    /// * `path` - The path to the root of a git repository (ie it should have a .git folder in it)
-    ///
-    /// # Returns
-    ///
-    /// Vec<Url> or Error
-    fn get_server_urls(path: &Path) -> Result<Vec<url::Url>, failure::Error> {
-        let repo = Repository::init(path)?;
-        Ok(repo
+    fn root(&self) -> &Path {
+        self.repo.workdir().unwrap_or_else(|| self.repo.path())
+    }
+
+    /// get the names and urls of the repository's remotes, in the order git reports
+    /// them. scp style remotes (eg `git@dd-git.d2.com:domains/x.git`) are converted
+    /// to ssh urls.
+    fn named_remotes(&self) -> Result<Vec<(String, url::Url)>, RemoteBuildError> {
+        Ok(self
+            .repo
             .remotes()?
             .iter()
-            .filter_map(|x| x) // remove Nones
-            .map(|x| repo.find_remote(x).ok()) // get remotes, converting Result -> Option
-            .filter_map(|x| x) // filter out None again
-            .map(|x| x.url().unwrap_or("").to_string()) // get url, unwrapping and converting None -> ""
-            .filter(|x| x != "") // filter out ""
-            .map(|x| url::Url::parse(&x))
-            .filter_map(Option::Some)
-            .map(|x| x.unwrap())
+            .flatten() // remove Nones
+            .filter_map(|name| {
+                let remote = self.repo.find_remote(name).ok()?;
+                let url = remote.url()?;
+                match parse_remote(url) {
+                    Ok(url) => Some((name.to_string(), url)),
+                    Err(e) => {
+                        debug!("skipping remote {} = {}: {}", name, url, e);
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    fn tags(&self) -> Result<Vec<String>, RemoteBuildError> {
+        Ok(self
+            .repo
+            .tag_names(None)?
+            .iter()
+            .filter_map(|x| x.map(|x| x.to_string()))
             .collect())
     }
+
+    /// The id of the commit HEAD points at
+    fn head_ref(&self) -> Result<String, RemoteBuildError> {
+        Ok(self.repo.head()?.peel_to_commit()?.id().to_string())
+    }
+
+    /// The id of the commit which the tag points at
+    fn tag_ref(&self, tag: &str) -> Result<Option<String>, RemoteBuildError> {
+        match self.repo.find_reference(&format!("refs/tags/{}", tag)) {
+            Ok(reference) => Ok(Some(reference.peel_to_commit()?.id().to_string())),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Untracked files are not considered to make the repository dirty
+    fn is_dirty(&self) -> Result<bool, RemoteBuildError> {
+        let mut options = StatusOptions::new();
+        options.include_untracked(false).include_ignored(false);
+        Ok(!self.repo.statuses(Some(&mut options))?.is_empty())
+    }
+
+    /// List the refs of the remote, as `git ls-remote` would, looking for the tag
+    fn remote_tag_exists(&self, url: &url::Url, tag: &str) -> Result<Option<bool>, RemoteBuildError> {
+        let mut remote = self.repo.remote_anonymous(url.as_str())?;
+        let connection = remote
+            .connect_auth(Direction::Fetch, Some(self.callbacks()?), None)
+            .map_err(|e| RemoteBuildError::VcsError(format!("unable to list the refs of {}: {}", url, e)))?;
+        let refname = format!("refs/tags/{}", tag);
+        Ok(Some(connection.list()?.iter().any(|head| head.name() == refname)))
+    }
 }
+
 impl Git {
-    /// get remote repositories for the local git repo in `path`.
-    ///
-    /// # Parameters
-    ///
-    /// * `path` - The path to the root of a git repository (ie it should have a .git folder in it)
+    // callbacks authenticating against remotes via the ssh agent, or git's
+    // credential helpers. Each is only tried once, so that a rejected credential
+    // results in an error rather than an endless loop.
+    fn callbacks<'a>(&self) -> Result<RemoteCallbacks<'a>, RemoteBuildError> {
+        let config = self.repo.config()?;
+        let mut tried = CredentialType::empty();
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(move |url, username, allowed| {
+            if allowed.contains(CredentialType::USERNAME) {
+                return Cred::username(username.unwrap_or("git"));
+            }
+            let untried = allowed - tried;
+            if untried.contains(CredentialType::SSH_KEY) {
+                tried |= CredentialType::SSH_KEY;
+                Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if untried.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                tried |= CredentialType::USER_PASS_PLAINTEXT;
+                Cred::credential_helper(&config, url, username)
+            } else if untried.contains(CredentialType::DEFAULT) {
+                tried |= CredentialType::DEFAULT;
+                Cred::default()
+            } else {
+                Err(git2::Error::from_str(&format!("unable to authenticate against {}", url)))
+            }
+        });
+        Ok(callbacks)
+    }
+
+    /// Create an annotated tag at HEAD, tagged by the user configured in git. An
+    /// existing tag is never moved.
     ///
     /// # Returns
     ///
-    pub fn get_remotes_strings(path: &str) -> Result<Vec<String>, failure::Error> {
-        let repo = Repository::init(path)?;
-        Ok(repo
+    /// The id of the commit which has been tagged, or Error
+    pub fn create_tag(&self, tag: &str, message: &str) -> Result<String, RemoteBuildError> {
+        let head = self.repo.head()?.peel_to_commit()?;
+        let signature = self.repo.signature()?;
+        self.repo
+            .tag(tag, head.as_object(), &signature, message, false)
+            .map_err(|e| match e.code() {
+                ErrorCode::Exists => RemoteBuildError::VcsError(format!("tag {} already exists", tag)),
+                _ => e.into(),
+            })?;
+        Ok(head.id().to_string())
+    }
+
+    /// Push the tag to the named remote
+    pub fn push_tag(&self, remote: &str, tag: &str) -> Result<(), RemoteBuildError> {
+        let mut remote = self.repo.find_remote(remote)?;
+        let refspec = format!("refs/tags/{0}:refs/tags/{0}", tag);
+        let mut rejection = None;
+        {
+            let mut callbacks = self.callbacks()?;
+            // the push succeeds even when the remote rejects the ref
+            callbacks.push_update_reference(|refname, status| {
+                if let Some(status) = status {
+                    rejection = Some(format!("{} was rejected: {}", refname, status));
+                }
+                Ok(())
+            });
+            let mut options = PushOptions::new();
+            options.remote_callbacks(callbacks);
+            remote.push(&[refspec.as_str()], Some(&mut options))?;
+        }
+        match rejection {
+            Some(rejection) => Err(RemoteBuildError::VcsError(rejection)),
+            None => Ok(()),
+        }
+    }
+
+    /// get the urls of the repository's remotes, as configured.
+    pub fn remote_strings(&self) -> Result<Vec<String>, RemoteBuildError> {
+        Ok(self
+            .repo
             .remotes()?
             .iter()
-            .filter_map(|x| x) // remove Nones
-            .map(|x| repo.find_remote(x).ok()) // get remotes, converting Result -> Option
-            .filter_map(|x| x) // filter out None again
-            .map(|x| x.url().unwrap_or("").to_string()) // get url, unwrapping and converting None -> ""
-            .filter(|x| x != "") // filter out ""
+            .flatten() // remove Nones
+            .filter_map(|x| self.repo.find_remote(x).ok()) // get remotes, discarding errors
+            .filter_map(|x| x.url().map(|x| x.to_string()))
+            .filter(|x| !x.is_empty())
             .collect())
     }
+}
 
-    /// get remote repositories for the local git repo in `path`.
-    ///
-    /// # Parameters
-    ///
-    /// * `path` - The path to the root of a git repository (ie it should have a .git folder in it)
-    ///
-    /// # Returns
-    ///
-    pub fn get_remotes_urls(path: &str) -> Result<Vec<url::Url>, failure::Error> {
-        let repo = Repository::init(path)?;
-        Ok(repo
-            .remotes()?
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::fs;
+
+    // create a git repository fixture, unique to the test, with a single tagged commit
+    fn fixture(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("git-{}", name));
+        let repo = Repository::init(&root).unwrap();
+        repo.remote("origin", "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git")
+            .unwrap();
+        fs::write(root.join("README.md"), "animtools\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("README.md")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("someone", "someone@d2.com").unwrap();
+        let commit = repo.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[]).unwrap();
+        repo.tag_lightweight("1.0.0", &repo.find_object(commit, None).unwrap(), false)
+            .unwrap();
+        root
+    }
+
+    #[test]
+    fn can_introspect_repo() {
+        let root = fixture("introspect");
+        let git = Git::open(&root).unwrap();
+        assert_eq!(git.system(), VcsSystem::Git);
+        assert_eq!(
+            git.remotes().unwrap()[0].as_str(),
+            "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git"
+        );
+        assert_eq!(git.tags().unwrap(), vec!["1.0.0"]);
+        assert!(git.tag_exists("1.0.0").unwrap());
+        assert!(!git.tag_exists("1.0.1").unwrap());
+        assert_eq!(git.head_ref().unwrap().len(), 40);
+        assert_eq!(git.tag_ref("1.0.0").unwrap(), Some(git.head_ref().unwrap()));
+        assert_eq!(git.tag_ref("1.0.1").unwrap(), None);
+
+        assert!(!git.is_dirty().unwrap());
+        fs::write(root.join("README.md"), "animtools2\n").unwrap();
+        assert!(git.is_dirty().unwrap());
+    }
+
+    #[test]
+    fn can_read_scp_style_remote() {
+        let root = fixture("scp");
+        Repository::open(&root)
+            .unwrap()
+            .remote("upstream", "git@dd-git.d2.com:domains/animation/animtools.git")
+            .unwrap();
+        let remotes = Git::open(&root).unwrap().remotes().unwrap();
+        assert_eq!(remotes.len(), 2);
+        assert!(remotes
             .iter()
-            .filter_map(|x| x) // remove Nones
-            .map(|x| repo.find_remote(x).ok()) // get remotes, converting Result -> Option
-            .filter_map(|x| x) // filter out None again
-            .map(|x| x.url().unwrap_or("").to_string()) // get url, unwrapping and converting None -> ""
-            .filter(|x| x != "") // filter out ""
-            .map(|x| url::Url::parse(&x))
-            .filter_map(Option::Some)
-            .map(|x| x.unwrap())
-            .collect())
+            .any(|x| x.as_str() == "ssh://git@dd-git.d2.com/domains/animation/animtools.git"));
+    }
+
+    #[test]
+    fn can_list_remote_tags() {
+        let upstream = fixture("ls-remote-upstream");
+        let clone = fixture("ls-remote-clone");
+        let url = url::Url::from_file_path(&upstream).unwrap();
+        let git = Git::open(&clone).unwrap();
+        assert_eq!(git.remote_tag_exists(&url, "1.0.0").unwrap(), Some(true));
+        assert_eq!(git.remote_tag_exists(&url, "1.0.1").unwrap(), Some(false));
+    }
+
+    #[test]
+    fn can_create_and_push_tag() {
+        let root = fixture("release");
+        let upstream = TempDir::new("git-release-upstream");
+        Repository::init_bare(&upstream).unwrap();
+        let repo = Repository::open(&root).unwrap();
+        repo.remote("upstream", url::Url::from_file_path(&upstream).unwrap().as_str())
+            .unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "someone").unwrap();
+        config.set_str("user.email", "someone@d2.com").unwrap();
+
+        let git = Git::open(&root).unwrap();
+        assert_eq!(git.create_tag("1.1.0", "release 1.1.0").unwrap(), git.head_ref().unwrap());
+        assert!(git.create_tag("1.1.0", "release 1.1.0").is_err());
+        let tag = repo.find_reference("refs/tags/1.1.0").unwrap().peel_to_tag().unwrap();
+        assert_eq!(tag.message(), Some("release 1.1.0"));
+
+        git.push_tag("upstream", "1.1.0").unwrap();
+        let upstream_url = url::Url::from_file_path(&upstream).unwrap();
+        assert_eq!(git.remote_tag_exists(&upstream_url, "1.1.0").unwrap(), Some(true));
+    }
+
+    #[test]
+    fn can_discover_repo_from_subdirectory() {
+        let root = fixture("discover");
+        fs::create_dir_all(root.join("src").join("python")).unwrap();
+        let git = Git::open(&root.join("src").join("python")).unwrap();
+        assert_eq!(git.root().canonicalize().unwrap(), root.canonicalize().unwrap());
+        assert_eq!(git.tags().unwrap(), vec!["1.0.0"]);
+    }
+
+    #[test]
+    fn can_open_submodule() {
+        let root = fixture("submodule");
+        let repo = Repository::open(&root).unwrap();
+        repo.submodule("ssh://git@dd-git.d2.com:2224/domains/animation/animlib.git", Path::new("animlib"), true)
+            .unwrap();
+        // the submodule's .git is a file, pointing into the superproject's .git/modules
+        assert!(root.join("animlib").join(".git").is_file());
+        let git = Git::open(&root.join("animlib")).unwrap();
+        assert_eq!(
+            git.root().canonicalize().unwrap(),
+            root.join("animlib").canonicalize().unwrap()
+        );
+        assert_eq!(
+            git.remotes().unwrap()[0].as_str(),
+            "ssh://git@dd-git.d2.com:2224/domains/animation/animlib.git"
+        );
+    }
+
+    #[test]
+    fn will_not_create_repo() {
+        let root = TempDir::new("git-none");
+        match Git::open(&root) {
+            Err(RemoteBuildError::VcsError(msg)) => assert!(msg.contains("is not within a git repository")),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("opened a repository in {:?}", root),
+        }
+        assert!(!root.join(".git").exists());
     }
 }
@@ -0,0 +1,291 @@
+//! hg.rs
+//!
+//! Introspect a local mercurial repository. Mercurial repositories are identified
+//! by their `.hg` directory, their remotes are read from the `[paths]` section of
+//! `.hg/hgrc`, their tags from the `.hgtags` file in the working copy, and the
+//! checked out revision from `.hg/dirstate`. Only `is_dirty` requires the `hg`
+//! command.
+use crate::{prelude::*, RemoteBuildError, VcsSystem};
+use log::debug;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The node recorded in .hgtags when a tag is removed
+const NULL_NODE: &str = "0000000000000000000000000000000000000000";
+
+/// The length, in bytes, of a node id
+const NODE_LEN: usize = 20;
+
+/// A local mercurial repository
+pub struct Hg {
+    root: PathBuf,
+}
+
+impl Vcs for Hg {
+    /// Open the mercurial repository housing `path`, which is identified by a `.hg`
+    /// directory in `path` or one of its parents.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - The path to a directory within a mercurial repository
+    ///
+    /// # Returns
+    ///
+    /// Hg or Error
+    fn open(path: &Path) -> Result<Self, RemoteBuildError> {
+        path.ancestors()
+            .find(|dir| dir.join(".hg").is_dir())
+            .map(|root| Hg {
+                root: root.to_path_buf(),
+            })
+            .ok_or_else(|| {
+                RemoteBuildError::VcsError(format!("{} is not within a mercurial repository", path.display()))
+            })
+    }
+
+    fn system(&self) -> VcsSystem {
+        VcsSystem::Mercurial
+    }
+
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// get the remote repositories, with `default` first.
+    fn named_remotes(&self) -> Result<Vec<(String, url::Url)>, RemoteBuildError> {
+        Ok(self
+            .paths()?
+            .into_iter()
+            .filter_map(|(name, value)| match url::Url::parse(&value) {
+                Ok(url) => Some((name, url)),
+                Err(e) => {
+                    // local clones are perfectly legitimate paths, but are no use to the build server
+                    debug!("skipping hg path {} = {}: {}", name, value, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Retrieve the tags recorded in the `.hgtags`, in the order in which they were
+    /// first tagged. Tags which have since been removed are omitted. A repository
+    /// without a .hgtags file has no tags.
+    fn tags(&self) -> Result<Vec<String>, RemoteBuildError> {
+        Ok(self.hgtags()?.into_iter().map(|(tag, _)| tag).collect())
+    }
+
+    /// The node id which the `.hgtags` records for the tag
+    fn tag_ref(&self, tag: &str) -> Result<Option<String>, RemoteBuildError> {
+        Ok(self.hgtags()?.into_iter().find(|(x, _)| x == tag).map(|(_, node)| node))
+    }
+
+    /// The node id of the working copy's first parent, which the dirstate starts with
+    fn head_ref(&self) -> Result<String, RemoteBuildError> {
+        let dirstate = self.root.join(".hg").join("dirstate");
+        let contents = fs::read(&dirstate)?;
+        if contents.len() < NODE_LEN {
+            return Err(RemoteBuildError::VcsError(format!("{} is truncated", dirstate.display())));
+        }
+        Ok(contents[..NODE_LEN].iter().map(|x| format!("{:02x}", x)).collect())
+    }
+
+    /// Unknown files are not considered to make the repository dirty
+    fn is_dirty(&self) -> Result<bool, RemoteBuildError> {
+        let output = Command::new("hg")
+            .args(["status", "--modified", "--added", "--removed", "--deleted"])
+            .current_dir(&self.root)
+            .output()?;
+        if !output.status.success() {
+            return Err(RemoteBuildError::ShellFnError(format!(
+                "`hg status` exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(!output.stdout.is_empty())
+    }
+}
+
+impl Hg {
+    // read the (tag, node) pairs from the .hgtags, if there is one
+    fn hgtags(&self) -> Result<Vec<(String, String)>, RemoteBuildError> {
+        let hgtags = self.root.join(".hgtags");
+        if !hgtags.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(parse_tags(&fs::read_to_string(&hgtags)?))
+    }
+
+    /// Retrieve the `[paths]` of the repository, as (name, location) pairs. `default`
+    /// is placed first, followed by `default-push`, followed by the rest in the order
+    /// in which they appear in the hgrc.
+    pub fn paths(&self) -> Result<Vec<(String, String)>, RemoteBuildError> {
+        let hgrc = self.root.join(".hg").join("hgrc");
+        let contents = fs::read_to_string(&hgrc).map_err(|e| {
+            RemoteBuildError::VcsError(format!("unable to read {}: {}", hgrc.display(), e))
+        })?;
+        let mut paths = parse_paths(&contents);
+        paths.sort_by_key(|(name, _)| match name.as_str() {
+            "default" => 0,
+            "default-push" => 1,
+            _ => 2,
+        });
+        Ok(paths)
+    }
+}
+
+// Parse the `[paths]` section of an hgrc. Later definitions of a path override
+// earlier ones, as they do in mercurial.
+fn parse_paths(contents: &str) -> Vec<(String, String)> {
+    let mut paths: Vec<(String, String)> = Vec::new();
+    let mut in_paths = false;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+        if trimmed.starts_with('[') {
+            in_paths = trimmed == "[paths]";
+            continue;
+        }
+        // continuation lines and directives (eg %include) are not paths
+        if !in_paths || line.starts_with(char::is_whitespace) || trimmed.starts_with('%') {
+            continue;
+        }
+        let mut pieces = trimmed.splitn(2, '=');
+        if let (Some(name), Some(value)) = (pieces.next(), pieces.next()) {
+            let (name, value) = (name.trim().to_string(), value.trim().to_string());
+            // sub-options (eg default:pushurl) are not paths in their own right
+            if name.contains(':') || value.is_empty() {
+                continue;
+            }
+            match paths.iter_mut().find(|(existing, _)| *existing == name) {
+                Some(entry) => entry.1 = value,
+                None => paths.push((name, value)),
+            }
+        }
+    }
+    paths
+}
+
+// Parse the contents of a .hgtags file, whose lines are of the form `<node> <tag>`.
+// Retagging moves a tag, and tagging the null node removes it. Returns (tag, node) pairs.
+fn parse_tags(contents: &str) -> Vec<(String, String)> {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for line in contents.lines() {
+        let mut pieces = line.trim().splitn(2, ' ');
+        let (node, tag) = match (pieces.next(), pieces.next()) {
+            (Some(node), Some(tag)) if !tag.trim().is_empty() => (node, tag.trim()),
+            _ => continue,
+        };
+        if node == NULL_NODE {
+            tags.retain(|(x, _)| x != tag);
+        } else if let Some(entry) = tags.iter_mut().find(|(x, _)| x == tag) {
+            // the tag has been moved
+            entry.1 = node.to_string();
+        } else {
+            tags.push((tag.to_string(), node.to_string()));
+        }
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    const HGRC: &str = r#"
+# generated by hg clone
+[ui]
+username = someone
+
+[paths]
+upstream = ssh://hg@dd-hg.d2.com//repos/legacy_upstream
+default = ssh://hg@dd-hg.d2.com//repos/legacy
+default:pushurl = ssh://hg@dd-hg.d2.com//repos/legacy_push
+local = /home/someone/src/legacy
+
+[extensions]
+rebase =
+"#;
+
+    const HGTAGS: &str = "\
+1111111111111111111111111111111111111111 1.0.0
+2222222222222222222222222222222222222222 1.1.0
+3333333333333333333333333333333333333333 1.2.0
+0000000000000000000000000000000000000000 1.1.0
+";
+
+    // create a mercurial repository fixture, unique to the test
+    fn fixture(name: &str, hgrc: Option<&str>, hgtags: Option<&str>) -> TempDir {
+        let root = TempDir::new(&format!("hg-{}", name));
+        fs::create_dir_all(root.join(".hg")).unwrap();
+        if let Some(hgrc) = hgrc {
+            fs::write(root.join(".hg").join("hgrc"), hgrc).unwrap();
+        }
+        if let Some(hgtags) = hgtags {
+            fs::write(root.join(".hgtags"), hgtags).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn can_identify_repo() {
+        let root = fixture("identify", None, None);
+        fs::create_dir_all(root.join("src")).unwrap();
+        assert_eq!(Hg::open(&root.join("src")).unwrap().root(), root.path());
+        let path = root.to_path_buf();
+        drop(root);
+        assert!(Hg::open(&path).is_err());
+    }
+
+    #[test]
+    fn can_get_remotes() {
+        let root = fixture("urls", Some(HGRC), None);
+        let hg = Hg::open(&root).unwrap();
+        let paths = hg.paths().unwrap();
+        let names = paths.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["default", "upstream", "local"]);
+
+        let urls = hg.remotes().unwrap();
+        let urls = urls.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+        assert_eq!(
+            urls,
+            vec!["ssh://hg@dd-hg.d2.com//repos/legacy", "ssh://hg@dd-hg.d2.com//repos/legacy_upstream"]
+        );
+    }
+
+    #[test]
+    fn missing_hgrc_is_an_error() {
+        let root = fixture("nohgrc", None, None);
+        assert!(Hg::open(&root).unwrap().paths().is_err());
+    }
+
+    #[test]
+    fn can_check_tags() {
+        let root = fixture("tags", Some(HGRC), Some(HGTAGS));
+        let hg = Hg::open(&root).unwrap();
+        assert_eq!(hg.tags().unwrap(), vec!["1.0.0", "1.2.0"]);
+        assert!(hg.tag_exists("1.2.0").unwrap());
+        // removed tags no longer exist
+        assert!(!hg.tag_exists("1.1.0").unwrap());
+        assert_eq!(hg.tag_ref("1.2.0").unwrap(), Some("3".repeat(40)));
+        assert_eq!(hg.tag_ref("1.1.0").unwrap(), None);
+
+        let root = fixture("notags", Some(HGRC), None);
+        assert!(Hg::open(&root).unwrap().tags().unwrap().is_empty());
+    }
+
+    #[test]
+    fn can_read_head_from_dirstate() {
+        let root = fixture("head", Some(HGRC), None);
+        let mut dirstate = vec![0xabu8; NODE_LEN];
+        dirstate.extend_from_slice(&[0u8; NODE_LEN]);
+        fs::write(root.join(".hg").join("dirstate"), dirstate).unwrap();
+        assert_eq!(Hg::open(&root).unwrap().head_ref().unwrap(), "ab".repeat(NODE_LEN));
+    }
+}
@@ -0,0 +1,148 @@
+//! inspect.rs
+//!
+//! Commands which present what packalaka knows about a package, without
+//! submitting anything: `tags <package>` lists the package's tags, and
+//! `info <package>` presents the package's gpi record. Both present a table
+//! by default, or json when asked.
+use crate::{
+    from_gpi::request::find_record,
+    gpi::Record,
+    packalaka_cache::CacheMode,
+    packalaka_client,
+    packalaka_tags::{PackageTag, PackageTagList},
+    version::Version,
+    Config, RemoteBuildError,
+};
+use log::debug;
+use prettytable::{cell, format, row, table, Table};
+use std::cmp::Ordering;
+
+/// Retrieve the tags in the list, most recent version first. Tags which do not
+/// parse as versions are placed last, by name.
+pub fn sorted_tags(tags: &PackageTagList) -> Vec<&PackageTag> {
+    let mut sorted = tags
+        .iter()
+        .map(|tag| (Version::parse(&tag.name).ok(), tag))
+        .collect::<Vec<(Option<Version>, &PackageTag)>>();
+    sorted.sort_by(|(lversion, ltag), (rversion, rtag)| match (lversion, rversion) {
+        (Some(left), Some(right)) => right.cmp(left),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => ltag.name.cmp(&rtag.name),
+    });
+    sorted.into_iter().map(|(_, tag)| tag).collect()
+}
+
+/// Generate a table presenting the tags
+pub fn tags_table(name: &str, tags: &[&PackageTag]) -> Table {
+    let mut table = table!([FYbH4c -> format!("Tags of {}", name).as_str()]);
+    table.add_row(row![FYb -> "Tag", FYb -> "Status", FYb -> "VCS", FYb -> "Flavors"]);
+    for tag in tags {
+        table.add_row(row![
+            Fwb -> tag.name.as_str(),
+            Fw -> tag.status.as_ref(),
+            Fw -> tag.uses.to_string().as_str(),
+            Fw -> tag.flavors().join(" , ").as_str()
+        ]);
+    }
+    table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+    table
+}
+
+/// Generate a table presenting the gpi record
+pub fn info_table(name: &str, record: &Record) -> Table {
+    let mut table = table!(
+        [FYbH7c -> format!("{} ({})", name, record.pkg_type()).as_str()],
+        [FYb -> "#", FYb -> "Link", FYb -> "VCS", FYb -> "Status", FYb -> "Submodules", FYb -> "Subdirectory", FYb -> "Tags"]
+    );
+    for (idx, source) in record.sources().iter().enumerate() {
+        let status = source.status().as_ref();
+        table.add_row(row![
+            Fw -> idx.to_string().as_str(),
+            Fwb -> source.link(),
+            Fw -> source.uses().to_string().as_str(),
+            if source.is_active() { cell!(Fg -> status) } else { cell!(Fy -> status) },
+            Fw -> if source.init_submodules() { "yes" } else { "no" },
+            Fw -> source.subdirectory().unwrap_or(""),
+            Fw -> source.tags()
+        ]);
+    }
+    table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+    table
+}
+
+/// List the tags of the package
+pub fn do_tags(
+    name: &str,
+    include_pre: bool,
+    json: bool,
+    cache_mode: CacheMode,
+) -> Result<(), RemoteBuildError> {
+    let config = Config::load()?;
+    let client = packalaka_client::cached_client(cache_mode, &config.cache)?;
+    // report unknown packages, with suggestions, rather than an empty list
+    find_record(client.as_ref(), name)?;
+    let tags = client.all_tags(name, include_pre)?;
+    debug!("tags {:#?}", tags);
+    let sorted = sorted_tags(&tags);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&sorted)?);
+    } else {
+        println!();
+        tags_table(name, &sorted).printstd();
+        println!();
+    }
+    Ok(())
+}
+
+/// Present the gpi record of the package
+pub fn do_info(name: &str, json: bool, cache_mode: CacheMode) -> Result<(), RemoteBuildError> {
+    let config = Config::load()?;
+    let client = packalaka_client::cached_client(cache_mode, &config.cache)?;
+    let record = find_record(client.as_ref(), name)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&record)?);
+    } else {
+        println!();
+        info_table(name, &record).printstd();
+        println!();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpi::GpiRecords;
+
+    #[test]
+    fn can_sort_tags() {
+        let tags = PackageTagList::from_str(
+            r#"[
+  {"link": "http://dd-git.d2.com/foo.git#tag=3.5.0.alpha2", "name": "3.5.0.alpha2", "status": "active", "uses": "git", "versions": []},
+  {"link": "http://dd-git.d2.com/foo.git#tag=experimental", "name": "experimental", "status": "active", "uses": "git", "versions": []},
+  {"link": "http://dd-git.d2.com/foo.git#tag=3.10.0", "name": "3.10.0", "status": "active", "uses": "git", "versions": []},
+  {"link": "http://dd-git.d2.com/foo.git#tag=3.5.0", "name": "3.5.0", "status": "active", "uses": "git", "versions": []}
+]"#,
+        )
+        .unwrap();
+        let names = sorted_tags(&tags).iter().map(|x| x.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["3.10.0", "3.5.0", "3.5.0.alpha2", "experimental"]);
+    }
+
+    #[test]
+    fn json_round_trips_through_parser() {
+        let data = r#"{
+  "animtools": {
+    "sources": [
+      {"initSubmodules": true, "link": "ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git",
+       "status": "active", "subdirectory": "tools", "tags": "%", "uses": "git"}
+    ],
+    "type": "source"
+  }
+}"#;
+        let records = GpiRecords::from_str(data).unwrap();
+        let json = serde_json::to_string(&records).unwrap();
+        assert_eq!(GpiRecords::from_str(&json).unwrap(), records);
+    }
+}
@@ -15,6 +15,12 @@ pub use vcs_system::VcsSystem;
 pub mod build_request;
 pub use build_request::*;
 
+pub mod repo_ref;
+pub use repo_ref::{RefKind, RepoRef};
+
+pub mod remote_url;
+pub use remote_url::UrlNormalizer;
+
 pub mod package_build_request;
 pub use package_build_request::*;
 
@@ -26,6 +32,21 @@ pub use build_param_type::*;
 
 pub mod constants;
 
+pub mod config;
+pub use config::Config;
+
+pub mod build_plan;
+pub use build_plan::BuildPlan;
+
+pub mod build_history;
+pub use build_history::BuildHistory;
+
+pub mod rebuild;
+
+pub mod release;
+
+pub mod inspect;
+
 pub mod build_server;
 pub use build_server::BuildServer;
 
@@ -36,27 +57,50 @@ pub mod errors;
 pub use errors::*;
 
 pub mod flavor;
-pub use flavor::Flavors;
+pub use flavor::{FlavorSelector, FlavorSpec, Flavors};
 
-// not used
-//pub mod from_manifest;
-//pub use from_manifest::minifest::Minifest;
+pub mod version;
+pub use version::{Version, VersionReq};
+
+pub mod pattern;
+pub use pattern::Pattern;
+
+pub mod from_manifest;
+pub use from_manifest::minifest::Minifest;
 
 pub mod svn;
 pub use svn::Svn;
 
+pub mod svn_layout;
+pub use svn_layout::{SvnInfo, SvnLayout};
+
 pub mod git;
 pub use git::Git;
 
+pub mod hg;
+pub use hg::Hg;
+
+pub mod perforce;
+pub use perforce::Perforce;
+
 pub mod machine_os;
 pub use machine_os::MachineOs;
 
 pub mod from_gpi;
 pub use from_gpi::gpi;
 pub use from_gpi::packalaka_tags;
+pub use from_gpi::packalaka_client::{self, PackalakaClient};
+pub use from_gpi::packalaka_cache::{self, CacheMode, PackalakaCache};
+pub use from_gpi::source_selector::{self, SourceSelector};
+pub use from_gpi::tag_spec::{self, TagSpec};
 
 pub mod utils;
 
+pub mod suggest;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
+
 pub mod prelude {
     pub use super::traits::*;
 }
@@ -1,7 +1,7 @@
 use log::{debug, error};
 use pkg_build_remote::{
     RemoteBuildError,
-    from_gpi, cli::Opt,
+    from_gpi, from_manifest, inspect, rebuild, release, CacheMode, Config, PackalakaCache, cli::{CacheCommand, Command, Opt},
 };
 use pretty_env_logger;
 
@@ -28,7 +28,29 @@ fn main_() -> Result<(), RemoteBuildError> {
     pretty_env_logger::init();
     debug!("Initialized");
     
-    let result = from_gpi::request::do_gpi(opts);
+    let result = match opts.cmd {
+        Some(Command::RebuildFailed{ref name, ref tag}) => {
+            rebuild::do_rebuild_failed(name, tag, opts.dry_run, opts.verbose)
+        }
+        Some(Command::Tags{ref name, include_pre, json}) => {
+            CacheMode::from_flags(opts.refresh, opts.offline)
+                .and_then(|mode| inspect::do_tags(name, include_pre, json, mode))
+        }
+        Some(Command::Info{ref name, json}) => {
+            CacheMode::from_flags(opts.refresh, opts.offline)
+                .and_then(|mode| inspect::do_info(name, json, mode))
+        }
+        Some(Command::Local(ref local)) => from_manifest::request::do_local(&opts, local),
+        Some(Command::Release(ref release)) => release::do_release(&opts, release),
+        Some(Command::Cache{cmd: CacheCommand::Clear}) => {
+            PackalakaCache::from_config(&Config::load()?.cache).and_then(|cache| {
+                cache.clear()?;
+                println!("Cleared {}", cache.root().display());
+                Ok(())
+            })
+        }
+        None => from_gpi::request::do_gpi(opts),
+    };
 
     match result {
         Err(e) => {
@@ -0,0 +1,186 @@
+//! pattern.rs
+//!
+//! Model the patterns users may supply on the command line in order to
+//! select items (flavors for instance) from a list. A pattern may be
+//! a literal name, a shell style glob (`vray4*`), or a regular expression,
+//! introduced by the `re:` prefix (`re:^vray4\.0`).
+use crate::RemoteBuildError;
+use regex::Regex;
+use std::fmt;
+
+/// The prefix which identifies a pattern as a regular expression
+pub const REGEX_PREFIX: &str = "re:";
+
+/// A pattern used to match names
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Matches a name exactly
+    Literal(String),
+    /// Shell style glob supporting `*`, `?` and `[...]`. Globs must match the
+    /// whole name.
+    Glob(String, Regex),
+    /// Regular expression. Unlike globs, regular expressions match anywhere
+    /// in the name unless anchored.
+    Regex(String, Regex),
+}
+
+impl Pattern {
+    /// Parse a pattern from a &str. Strings beginning with `re:` are treated as
+    /// regular expressions, strings containing glob metacharacters are treated as
+    /// globs, and everything else is a literal.
+    pub fn parse(input: &str) -> Result<Self, RemoteBuildError> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(RemoteBuildError::PatternError("empty pattern".into()));
+        }
+        if let Some(expr) = input.strip_prefix(REGEX_PREFIX) {
+            let re = Regex::new(expr).map_err(|e| {
+                RemoteBuildError::PatternError(format!("invalid regex '{}': {}", expr, e))
+            })?;
+            return Ok(Pattern::Regex(expr.to_string(), re));
+        }
+        if input.contains(&['*', '?', '['][..]) {
+            let re = Regex::new(&glob_to_regex(input)).map_err(|e| {
+                RemoteBuildError::PatternError(format!("invalid glob '{}': {}", input, e))
+            })?;
+            return Ok(Pattern::Glob(input.to_string(), re));
+        }
+        Ok(Pattern::Literal(input.to_string()))
+    }
+
+    /// Parse a comma separated list of patterns. Commas within `{}`, `[]` or `()`
+    /// (eg the regex quantifier `{1,2}`), or escaped by a backslash, don't separate
+    /// patterns.
+    pub fn parse_list(input: &str) -> Result<Vec<Self>, RemoteBuildError> {
+        split_list(input)
+            .into_iter()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(Pattern::parse)
+            .collect()
+    }
+
+    /// Test whether the supplied name matches the pattern
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Pattern::Literal(lit) => lit == name,
+            Pattern::Glob(_, re) | Pattern::Regex(_, re) => re.is_match(name),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Literal(lit) => write!(f, "{}", lit),
+            Pattern::Glob(glob, _) => write!(f, "{}", glob),
+            Pattern::Regex(expr, _) => write!(f, "{}{}", REGEX_PREFIX, expr),
+        }
+    }
+}
+
+// Split the list at the commas which aren't nested within brackets or escaped
+fn split_list(input: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut escaped = false;
+    let mut start = 0;
+    for (idx, c) in input.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                items.push(&input[start..idx]);
+                start = idx + 1;
+            }
+            _ => (),
+        }
+    }
+    items.push(&input[start..]);
+    items
+}
+
+// Convert a shell style glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::with_capacity(glob.len() * 2 + 2);
+    re.push('^');
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => re.push_str(".*"),
+            '?' if !in_class => re.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                re.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                re.push(']');
+            }
+            '!' if in_class && re.ends_with('[') => re.push('^'),
+            _ if in_class => re.push(c),
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_literal() {
+        let pattern = Pattern::parse("vray4.0.29259_for_maya2018").unwrap();
+        assert!(pattern.matches("vray4.0.29259_for_maya2018"));
+        assert!(!pattern.matches("vray4.0.29259_for_maya2019"));
+    }
+
+    #[test]
+    fn can_match_glob() {
+        let pattern = Pattern::parse("vray4*").unwrap();
+        assert!(pattern.matches("vray4.0.29259_for_maya2018"));
+        assert!(!pattern.matches("vray3.6.27936_for_maya2018"));
+        let pattern = Pattern::parse("vray?.?.*_for_maya201[78]").unwrap();
+        assert!(pattern.matches("vray4.0.29259_for_maya2018"));
+        assert!(!pattern.matches("vray4.0.29259_for_maya2016"));
+    }
+
+    #[test]
+    fn glob_escapes_regex_metacharacters() {
+        let pattern = Pattern::parse("vray4.0*").unwrap();
+        assert!(!pattern.matches("vray4x0.29259"));
+        let pattern = Pattern::parse("^").unwrap();
+        assert!(pattern.matches("^"));
+    }
+
+    #[test]
+    fn can_match_regex() {
+        let pattern = Pattern::parse(r"re:maya201[78]$").unwrap();
+        assert!(pattern.matches("vray4.0.29259_for_maya2018"));
+        assert!(!pattern.matches("vray4.0.29259_for_maya2016"));
+    }
+
+    #[test]
+    fn can_catch_invalid_regex() {
+        assert!(Pattern::parse("re:vray(").is_err());
+    }
+
+    #[test]
+    fn can_parse_list() {
+        let patterns = Pattern::parse_list("vray4*, re:maya2018$,^").unwrap();
+        assert_eq!(patterns.len(), 3);
+        assert_eq!(patterns[1].to_string(), "re:maya2018$");
+
+        // commas within a quantifier or a class belong to the pattern
+        let patterns = Pattern::parse_list(r"re:^maya\d{1,2}$,vray[3,4]*").unwrap();
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[0].to_string(), r"re:^maya\d{1,2}$");
+        assert!(patterns[0].matches("maya20"));
+        assert!(!patterns[0].matches("maya2018"));
+        assert!(patterns[1].matches("vray4.0"));
+    }
+}
@@ -0,0 +1,365 @@
+//! perforce.rs
+//!
+//! Introspect a local perforce workspace. A workspace is identified by the
+//! config file named by `P4CONFIG` (`.p4config` by default), found in the
+//! supplied directory or one of its parents. The depot path which the workspace
+//! maps becomes the repo url (eg `p4://perforce:1666//depot/plugins/foo`), and
+//! labels on the depot path play the role of tags.
+//!
+//! Perforce is queried via the `p4` command, by way of a `P4Runner`, so that the
+//! command may be swapped out (eg in tests, which have no perforce server).
+use crate::{prelude::*, RemoteBuildError, VcsSystem};
+use log::debug;
+use std::{
+    env,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The name of the perforce config file, when P4CONFIG is not set
+pub const DEFAULT_P4CONFIG: &str = ".p4config";
+
+/// Runs p4 commands in a workspace, returning stdout
+pub trait P4Runner {
+    /// Run `p4` with the supplied arguments, from the supplied directory
+    fn run(&self, cwd: &Path, args: &[&str]) -> Result<String, RemoteBuildError>;
+}
+
+/// Runs p4 commands by shelling out to the `p4` command
+#[derive(Debug, Default, Clone)]
+pub struct CommandP4Runner;
+
+impl P4Runner for CommandP4Runner {
+    fn run(&self, cwd: &Path, args: &[&str]) -> Result<String, RemoteBuildError> {
+        debug!("p4 {}", args.join(" "));
+        let output = Command::new("p4").args(args).current_dir(cwd).output()?;
+        if !output.status.success() {
+            return Err(RemoteBuildError::VcsError(format!(
+                "`p4 {}` exited with {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        String::from_utf8(output.stdout)
+            .map_err(|e| RemoteBuildError::Utf8Error(e.utf8_error().valid_up_to()))
+    }
+}
+
+/// The settings read from a perforce config file
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct P4Config {
+    /// The directory housing the config file, which is the root of the workspace
+    pub root: PathBuf,
+    pub port: Option<String>,
+    pub client: Option<String>,
+    pub user: Option<String>,
+}
+
+impl P4Config {
+    /// Parse the contents of a config file, found in `root`
+    pub fn parse<P: Into<PathBuf>>(root: P, contents: &str) -> Self {
+        let mut config = P4Config {
+            root: root.into(),
+            ..P4Config::default()
+        };
+        for line in contents.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut pieces = line.splitn(2, '=');
+            let value = match (pieces.next(), pieces.next()) {
+                (Some(key), Some(value)) if !value.trim().is_empty() => (key.trim(), value.trim().to_string()),
+                _ => continue,
+            };
+            match value {
+                ("P4PORT", value) => config.port = Some(value),
+                ("P4CLIENT", value) => config.client = Some(value),
+                ("P4USER", value) => config.user = Some(value),
+                _ => (),
+            }
+        }
+        config
+    }
+
+    /// Find the config file named `name` in `path` or one of its parents
+    pub fn find(path: &Path, name: &str) -> Option<Self> {
+        path.ancestors()
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+            .and_then(|file| {
+                let contents = fs::read_to_string(&file).ok()?;
+                Some(Self::parse(file.parent()?, &contents))
+            })
+    }
+
+    /// The global options which select this config's server, workspace and user
+    fn global_args(&self) -> Vec<&str> {
+        let mut args = Vec::new();
+        for (flag, value) in &[("-p", &self.port), ("-c", &self.client), ("-u", &self.user)] {
+            if let Some(value) = value {
+                args.push(*flag);
+                args.push(value.as_str());
+            }
+        }
+        args
+    }
+
+    /// Convert P4PORT (eg `ssl:perforce:1666`) to the scheme and authority of a
+    /// repo url (eg `p4ssl://perforce:1666`)
+    fn server(&self) -> Result<String, RemoteBuildError> {
+        let port = self.port.as_deref().ok_or_else(|| {
+            RemoteBuildError::VcsError(format!("P4PORT is not set in the p4 config in {}", self.root.display()))
+        })?;
+        let mut pieces = port.splitn(2, ':');
+        let (scheme, address) = match (pieces.next(), pieces.next()) {
+            (Some(protocol), Some(rest)) if protocol.starts_with("ssl") => ("p4ssl", rest),
+            (Some(protocol), Some(rest)) if protocol.starts_with("tcp") => ("p4", rest),
+            _ => ("p4", port),
+        };
+        Ok(format!("{}://{}", scheme, address))
+    }
+}
+
+/// The name of the perforce config file, from P4CONFIG
+pub fn p4config_name() -> String {
+    env::var("P4CONFIG").unwrap_or_else(|_| DEFAULT_P4CONFIG.to_string())
+}
+
+/// A perforce workspace, whose depot path and labels are queried via a P4Runner.
+pub struct Perforce<R = CommandP4Runner> {
+    runner: R,
+    config: P4Config,
+}
+
+impl<R: P4Runner> Perforce<R> {
+    /// Open the workspace housing `path`, identified by the config file named
+    /// `config_name`, using the supplied runner
+    pub fn open_with(runner: R, path: &Path, config_name: &str) -> Result<Self, RemoteBuildError> {
+        let config = P4Config::find(path, config_name).ok_or_else(|| {
+            RemoteBuildError::VcsError(format!(
+                "no {} found in {} or its parents",
+                config_name,
+                path.display()
+            ))
+        })?;
+        Ok(Self { runner, config })
+    }
+
+    /// Retrieve the config of the workspace
+    pub fn config(&self) -> &P4Config {
+        &self.config
+    }
+
+    // run p4, with the workspace's config, from the root of the workspace
+    fn run(&self, args: &[&str]) -> Result<String, RemoteBuildError> {
+        let mut full = self.config.global_args();
+        full.push("-ztag");
+        full.extend_from_slice(args);
+        self.runner.run(&self.config.root, &full)
+    }
+
+    /// Retrieve the depot path (eg `//depot/plugins/foo`) mapped by the workspace root
+    pub fn depot_path(&self) -> Result<String, RemoteBuildError> {
+        let output = self.run(&["where", "./..."])?;
+        ztag_values(&output, "depotFile")
+            .into_iter()
+            // excluded mappings are prefixed with '-'
+            .find(|x| !x.starts_with('-'))
+            .map(|x| x.trim_end_matches("/...").to_string())
+            .ok_or_else(|| {
+                RemoteBuildError::VcsError(format!("{} is not mapped to a depot path", self.config.root.display()))
+            })
+    }
+
+    /// Retrieve the url of the depot path mapped by the workspace (eg `p4://perforce:1666//depot/plugins/foo`)
+    pub fn depot_url(&self) -> Result<url::Url, RemoteBuildError> {
+        Ok(url::Url::parse(&format!("{}{}", self.config.server()?, self.depot_path()?))?)
+    }
+}
+
+impl<R: P4Runner + Default> Vcs for Perforce<R> {
+    /// Open the workspace housing `path`, ie the directory which, along with its
+    /// parents, houses the config file named by P4CONFIG.
+    fn open(path: &Path) -> Result<Self, RemoteBuildError> {
+        Self::open_with(R::default(), path, &p4config_name())
+    }
+
+    fn system(&self) -> VcsSystem {
+        VcsSystem::Perforce
+    }
+
+    fn root(&self) -> &Path {
+        &self.config.root
+    }
+
+    /// The url of the depot path mapped by the workspace, named `depot`
+    fn named_remotes(&self) -> Result<Vec<(String, url::Url)>, RemoteBuildError> {
+        Ok(vec![("depot".to_string(), self.depot_url()?)])
+    }
+
+    /// The labels applied to the depot path mapped by the workspace
+    fn tags(&self) -> Result<Vec<String>, RemoteBuildError> {
+        let output = self.run(&["labels", &format!("{}/...", self.depot_path()?)])?;
+        Ok(ztag_values(&output, "label"))
+    }
+
+    /// The most recent changelist synced to the workspace
+    fn head_ref(&self) -> Result<String, RemoteBuildError> {
+        let output = self.run(&["changes", "-m1", "./...#have"])?;
+        ztag_values(&output, "change").into_iter().next().ok_or_else(|| {
+            RemoteBuildError::VcsError(format!("nothing has been synced to {}", self.config.root.display()))
+        })
+    }
+
+    /// The most recent changelist included in the label
+    fn tag_ref(&self, tag: &str) -> Result<Option<String>, RemoteBuildError> {
+        if !self.tag_exists(tag)? {
+            return Ok(None);
+        }
+        let output = self.run(&["changes", "-m1", &format!("./...@{}", tag)])?;
+        Ok(ztag_values(&output, "change").into_iter().next())
+    }
+
+    /// The workspace is dirty when files are opened for edit, add, delete etc
+    fn is_dirty(&self) -> Result<bool, RemoteBuildError> {
+        let output = self.run(&["opened", "./..."])?;
+        Ok(!ztag_values(&output, "depotFile").is_empty())
+    }
+
+    /// Labels live on the server, so there is nothing to push
+    fn remote_tag_exists(&self, _url: &url::Url, tag: &str) -> Result<Option<bool>, RemoteBuildError> {
+        Ok(Some(self.tag_exists(tag)?))
+    }
+}
+
+// Retrieve the values of the supplied field from `p4 -ztag` output, whose lines
+// are of the form `... <field> <value>`
+fn ztag_values(output: &str, field: &str) -> Vec<String> {
+    let prefix = format!("... {} ", field);
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix(prefix.as_str()))
+        .map(|value| value.trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::cell::RefCell;
+
+    const WHERE: &str = "\
+... depotFile //depot/plugins/vray_tools/...
+... clientFile //someone_ws/vray_tools/...
+... path /home/someone/ws/vray_tools/...
+";
+
+    const LABELS: &str = "\
+... label 1.0.0
+... Update 2020/01/01 10:00:00
+... Options unlocked noautoreload
+
+... label 1.1.0
+... Update 2020/02/01 10:00:00
+... Options unlocked noautoreload
+";
+
+    const CHANGES: &str = "\
+... change 12345
+... time 1580000000
+... user someone
+";
+
+    // a runner which answers `where`, `labels`, `changes` and `opened`, recording
+    // the commands it runs
+    #[derive(Default)]
+    struct MockRunner {
+        commands: RefCell<Vec<String>>,
+    }
+
+    impl P4Runner for MockRunner {
+        fn run(&self, _cwd: &Path, args: &[&str]) -> Result<String, RemoteBuildError> {
+            self.commands.borrow_mut().push(args.join(" "));
+            if args.contains(&"where") {
+                Ok(WHERE.to_string())
+            } else if args.contains(&"labels") {
+                Ok(LABELS.to_string())
+            } else if args.contains(&"changes") {
+                Ok(CHANGES.to_string())
+            } else if args.contains(&"opened") {
+                Ok(String::new())
+            } else {
+                Err(RemoteBuildError::VcsError(format!("unexpected command {:?}", args)))
+            }
+        }
+    }
+
+    // create a workspace fixture, unique to the test, returning a subdirectory of it
+    fn fixture(name: &str) -> (TempDir, PathBuf) {
+        let root = TempDir::new(&format!("p4-{}", name));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join(".p4config.test"),
+            "P4PORT=ssl:perforce.d2.com:1666\nP4CLIENT=someone_ws\n# P4USER=nobody\n",
+        )
+        .unwrap();
+        let subdir = root.join("src");
+        (root, subdir)
+    }
+
+    #[test]
+    fn can_find_config() {
+        let (root, subdir) = fixture("config");
+        let config = P4Config::find(&subdir, ".p4config.test").unwrap();
+        assert_eq!(config.root, root.path());
+        assert_eq!(config.port.as_deref(), Some("ssl:perforce.d2.com:1666"));
+        assert_eq!(config.client.as_deref(), Some("someone_ws"));
+        assert_eq!(config.user, None);
+        assert!(P4Config::find(&subdir, ".p4config.missing").is_none());
+    }
+
+    #[test]
+    fn can_get_depot_url() {
+        let (root, subdir) = fixture("url");
+        let p4 = Perforce::open_with(MockRunner::default(), &subdir, ".p4config.test").unwrap();
+        assert_eq!(p4.root(), root.path());
+        assert_eq!(p4.depot_path().unwrap(), "//depot/plugins/vray_tools");
+        assert_eq!(
+            p4.remotes().unwrap()[0].as_str(),
+            "p4ssl://perforce.d2.com:1666//depot/plugins/vray_tools"
+        );
+        assert_eq!(
+            p4.runner.commands.borrow()[0],
+            "-p ssl:perforce.d2.com:1666 -c someone_ws -ztag where ./..."
+        );
+    }
+
+    #[test]
+    fn can_treat_labels_as_tags() {
+        let (_root, subdir) = fixture("labels");
+        let p4 = Perforce::open_with(MockRunner::default(), &subdir, ".p4config.test").unwrap();
+        assert_eq!(p4.tags().unwrap(), vec!["1.0.0", "1.1.0"]);
+        assert!(p4.tag_exists("1.1.0").unwrap());
+        assert!(!p4.tag_exists("1.2.0").unwrap());
+        assert!(p4
+            .runner
+            .commands
+            .borrow()
+            .iter()
+            .any(|x| x.ends_with("labels //depot/plugins/vray_tools/...")));
+    }
+
+    #[test]
+    fn can_report_head_and_dirty() {
+        let (_root, subdir) = fixture("head");
+        let p4 = Perforce::open_with(MockRunner::default(), &subdir, ".p4config.test").unwrap();
+        assert_eq!(p4.head_ref().unwrap(), "12345");
+        assert_eq!(p4.tag_ref("1.1.0").unwrap(), Some("12345".to_string()));
+        assert_eq!(p4.tag_ref("2.0.0").unwrap(), None);
+        assert!(!p4.is_dirty().unwrap());
+        assert!(Perforce::open_with(MockRunner::default(), &subdir, ".p4config.missing").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! rebuild.rs
+//!
+//! Resubmit the distributions of a package and tag whose most recent
+//! BuildDistributionPipeline run failed. The parameters of the failed
+//! runs are retrieved from the build server, so the resubmitted requests
+//! are identical to the originals.
+use crate::{
+    utils::{confirm, UserBuildRequest},
+    BuildRequest, BuildServer, RemoteBuildError,
+};
+use log::{debug, info};
+use prettytable::{cell, format, row, table};
+
+/// Look up the failed distributions of the package and tag, and resubmit
+/// them once the user has confirmed.
+pub fn do_rebuild_failed(
+    name: &str,
+    tag: &str,
+    dry_run: bool,
+    verbose: bool,
+) -> Result<(), RemoteBuildError> {
+    let build_server = BuildServer::default();
+
+    info!("retrieving build history");
+    let history = build_server.build_history()?;
+    let failed = history.failed(name, tag);
+    debug!("failed builds {:#?}", failed);
+
+    if failed.is_empty() {
+        println!("No failed distributions found for {}-{}", name, tag);
+        return Ok(());
+    }
+
+    let requests = failed
+        .iter()
+        .map(|build| build.to_build_request())
+        .collect::<Result<Vec<BuildRequest>, RemoteBuildError>>()?;
+
+    let mut table = table!(
+        [FYbH5c -> format!("Failed Distributions of {}-{}", name, tag).as_str()],
+        [FYb -> "Build", FYb -> "Result", FYb -> "Flavor", FYb -> "Platform", FYb -> "VCS Repo"]
+    );
+    for (build, request) in failed.iter().zip(requests.iter()) {
+        table.add_row(row![
+            Fw -> format!("#{}", build.number).as_str(),
+            Fyb -> build.result().to_string().as_str(),
+            Fw -> request.flavor.as_str(),
+            Fw -> request.platform.to_string().as_str(),
+            Fw -> request.repo.as_str()
+        ]);
+    }
+    table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+    println!();
+    table.printstd();
+    println!();
+
+    if !dry_run && !confirm(&format!("Do you wish to resubmit {} distribution(s)?", requests.len())) {
+        println!("User cancelled build request");
+        return Ok(());
+    }
+
+    for request in requests {
+        debug!("{:?}", request);
+        build_server.request_build(UserBuildRequest::Distribution(request), verbose, dry_run)?;
+    }
+    Ok(())
+}
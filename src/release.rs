@@ -0,0 +1,322 @@
+//! release.rs
+//!
+//! Release a package from a git working copy in one step: tag HEAD, push the tag,
+//! have the build server scan the package for the new tag, and build it. All of
+//! the steps are presented up front, and a dry run stops there. A release which
+//! fails after tagging is resumed by running it again.
+use crate::{
+    cli::{Opt, ReleaseOpt},
+    config::Config,
+    constants::{TAG_SCAN_POLL_SECS, TAG_SCAN_TIMEOUT_SECS},
+    from_manifest::{
+        checks::{CheckStatus, Overrides, WorkingCopyChecks},
+        request::select_remote,
+    },
+    traits::*,
+    utils::{confirm, UserBuildRequest},
+    BuildServer, Git, Minifest, PackageBuildRequest, RemoteBuildError,
+};
+use log::{debug, info};
+use prettytable::{cell, format, row, table};
+use std::{
+    env, thread,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// A single step of a release
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ReleaseStep {
+    /// Create an annotated tag at the commit
+    Tag { tag: String, commit: String, message: String },
+    /// Push the tag to the remote
+    Push { tag: String, remote: String, url: Url },
+    /// Ask the build server to scan the package for tags
+    Scan { package: String, route: Url },
+    /// Wait for the build server to discover the tag
+    Wait { package: String, tag: String },
+    /// Build the tag
+    Build { package: String, tag: String, route: Url },
+}
+
+impl ReleaseStep {
+    /// A short name for the step
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReleaseStep::Tag { .. } => "Tag",
+            ReleaseStep::Push { .. } => "Push",
+            ReleaseStep::Scan { .. } => "Scan",
+            ReleaseStep::Wait { .. } => "Wait",
+            ReleaseStep::Build { .. } => "Build",
+        }
+    }
+
+    /// Describe what the step does
+    pub fn description(&self) -> String {
+        match self {
+            ReleaseStep::Tag { tag, commit, message } => {
+                format!("create the annotated tag {} at {} (\"{}\")", tag, commit, message)
+            }
+            ReleaseStep::Push { tag, remote, url } => format!("push refs/tags/{} to {} ({})", tag, remote, url),
+            ReleaseStep::Scan { route, .. } => format!("POST {}", route),
+            ReleaseStep::Wait { package, tag } => format!(
+                "wait up to {} seconds for the build server to discover {}-{}",
+                TAG_SCAN_TIMEOUT_SECS, package, tag
+            ),
+            ReleaseStep::Build { route, .. } => format!("POST {}", route),
+        }
+    }
+}
+
+/// Assemble the steps which release the tag of the package.
+///
+/// # Parameters
+///
+/// * `build_server` - The build server which builds the release
+/// * `package` - The name of the package
+/// * `tag` - The tag to create
+/// * `commit` - The commit to tag
+/// * `message` - The message of the annotated tag
+/// * `remote` - The name and url of the remote to push the tag to
+pub fn release_steps(
+    build_server: &BuildServer,
+    package: &str,
+    tag: &str,
+    commit: &str,
+    message: &str,
+    remote: (&str, &Url),
+) -> Result<Vec<ReleaseStep>, RemoteBuildError> {
+    let scan = build_server
+        .scan_route(package)
+        .ok_or_else(|| RemoteBuildError::EmptyError("unable to unwrap scan route".into()))?;
+    let build = build_server
+        .request_build_route(package, tag)
+        .ok_or_else(|| RemoteBuildError::EmptyError("unable to unwrap request_build_route".into()))?;
+    Ok(vec![
+        ReleaseStep::Tag {
+            tag: tag.to_string(),
+            commit: commit.to_string(),
+            message: message.to_string(),
+        },
+        ReleaseStep::Push {
+            tag: tag.to_string(),
+            remote: remote.0.to_string(),
+            url: remote.1.clone(),
+        },
+        ReleaseStep::Scan {
+            package: package.to_string(),
+            route: scan,
+        },
+        ReleaseStep::Wait {
+            package: package.to_string(),
+            tag: tag.to_string(),
+        },
+        ReleaseStep::Build {
+            package: package.to_string(),
+            tag: tag.to_string(),
+            route: build,
+        },
+    ])
+}
+
+// carry out a single step of the release
+fn execute(step: &ReleaseStep, git: &Git, build_server: &BuildServer, verbose: bool) -> Result<(), RemoteBuildError> {
+    match step {
+        ReleaseStep::Tag { tag, message, .. } => {
+            git.create_tag(tag, message)?;
+        }
+        ReleaseStep::Push { tag, remote, .. } => git.push_tag(remote, tag)?,
+        ReleaseStep::Scan { package, .. } => build_server.request_scan(package)?,
+        ReleaseStep::Wait { package, tag } => {
+            let start = Instant::now();
+            while !build_server.has_tag_job(package, tag)? {
+                if start.elapsed() > Duration::from_secs(TAG_SCAN_TIMEOUT_SECS) {
+                    return Err(RemoteBuildError::TagResolutionError(format!(
+                        "the build server did not discover {}-{} within {} seconds",
+                        package, tag, TAG_SCAN_TIMEOUT_SECS
+                    )));
+                }
+                debug!("waiting for the build server to discover {}-{}", package, tag);
+                thread::sleep(Duration::from_secs(TAG_SCAN_POLL_SECS));
+            }
+        }
+        ReleaseStep::Build { package, tag, .. } => {
+            build_server.request_build(
+                UserBuildRequest::Package(PackageBuildRequest::new(package.as_str(), tag.as_str())),
+                verbose,
+                false,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// A release which failed part way through may be resumed, as long as the tag is
+// still at HEAD. Pushing the tag again is harmless if it made it that far. A tag
+// elsewhere is an error.
+fn is_resumable(repo: &dyn Vcs, tag: &str) -> Result<bool, RemoteBuildError> {
+    let head = repo.head_ref()?;
+    match repo.tag_ref(tag)? {
+        Some(tagged) if tagged == head => Ok(true),
+        Some(tagged) => Err(RemoteBuildError::VcsError(format!(
+            "tag {} already exists at {}, rather than HEAD ({}). Use `pkg-build-remote local` to build it",
+            tag, tagged, head
+        ))),
+        None => Ok(false),
+    }
+}
+
+/// Release the version of the package in the git working copy
+pub fn do_release(opts: &Opt, release: &ReleaseOpt) -> Result<(), RemoteBuildError> {
+    let project_path = match release.project_path {
+        Some(ref path) => path.clone(),
+        None => env::current_dir()?,
+    };
+    let git = Git::open(&project_path)?;
+    let manifest = Minifest::from_disk(Some(git.root()))?;
+    let tag = release.version.as_str();
+    let message = release
+        .message
+        .clone()
+        .unwrap_or_else(|| format!("Release {} {}", manifest.name, tag));
+    debug!("releasing {}-{} from {:?}", manifest.name, tag, git.root());
+
+    let config = Config::load()?;
+    let (remote, url) = select_remote(git.named_remotes()?, release.remote.as_deref(), &config.remotes.preferred)?;
+    let head = git.head_ref()?;
+    let resuming = is_resumable(&git, tag)?;
+    let overrides = Overrides {
+        dirty: release.allow_dirty,
+        head: false,
+        version: release.allow_version_mismatch,
+    };
+    let checks = if resuming {
+        WorkingCopyChecks::run(&git, tag, Some(&manifest.version), overrides)
+    } else {
+        WorkingCopyChecks::before_tagging(&git, tag, Some(&manifest.version), overrides)
+    };
+
+    let build_server = BuildServer::default();
+    let mut steps = release_steps(&build_server, &manifest.name, tag, &head, &message, (&remote, &url))?;
+    if resuming {
+        println!("Tag {} already exists at HEAD. Resuming its release", tag);
+        steps.retain(|step| !matches!(step, ReleaseStep::Tag { .. }));
+    }
+
+    let mut table = table!([FYbH3c -> format!("Release {}-{}", manifest.name, tag).as_str()]);
+    for (idx, step) in steps.iter().enumerate() {
+        table.add_row(row![Fw -> (idx + 1).to_string().as_str(), FYb -> step.name(), Fwb -> step.description().as_str()]);
+    }
+    for check in checks.iter() {
+        let status = check.status.to_string();
+        match check.status {
+            CheckStatus::Failed(_) => table.add_row(row![Fw -> "", FYb -> check.name, Frb -> status.as_str()]),
+            _ => table.add_row(row![Fw -> "", FYb -> check.name, Fwb -> status.as_str()]),
+        };
+    }
+    table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+    println!();
+    table.printstd();
+    println!();
+    checks.verify()?;
+
+    if opts.dry_run {
+        println!("Dry run. Nothing has been tagged, pushed or built.");
+        return Ok(());
+    }
+    if !confirm(&format!("Do you wish to release {}-{}?", manifest.name, tag)) {
+        println!("User cancelled release");
+        return Ok(());
+    }
+
+    for (idx, step) in steps.iter().enumerate() {
+        info!("step {}: {}", idx + 1, step.description());
+        if let Err(e) = execute(step, &git, &build_server, opts.verbose) {
+            if idx > 0 || resuming {
+                println!(
+                    "The release stopped at the {} step. Run `pkg-build-remote release {}` again to resume it",
+                    step.name(),
+                    tag
+                );
+            }
+            return Err(e);
+        }
+        println!("{}/{} {}: done", idx + 1, steps.len(), step.name());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_describe_release() {
+        let url = Url::parse("ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git").unwrap();
+        let steps = release_steps(
+            &BuildServer::default(),
+            "animtools",
+            "1.1.0",
+            "abc123",
+            "Release animtools 1.1.0",
+            ("upstream", &url),
+        )
+        .unwrap();
+        let names = steps.iter().map(|x| x.name()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Tag", "Push", "Scan", "Wait", "Build"]);
+        assert_eq!(
+            steps[0].description(),
+            "create the annotated tag 1.1.0 at abc123 (\"Release animtools 1.1.0\")"
+        );
+        assert_eq!(
+            steps[1].description(),
+            "push refs/tags/1.1.0 to upstream (ssh://git@dd-git.d2.com:2224/domains/animation/animtools.git)"
+        );
+        assert!(steps[2].description().ends_with("/job/Packages/job/animtools/build?delay=0"));
+        assert!(steps[4].description().ends_with("/job/Packages/job/animtools/job/1.1.0/build"));
+    }
+
+    // a working copy at abc, where 1.0.0 has been tagged
+    struct Tagged;
+
+    impl Vcs for Tagged {
+        fn open(_path: &std::path::Path) -> Result<Self, RemoteBuildError> {
+            Ok(Tagged)
+        }
+        fn system(&self) -> crate::VcsSystem {
+            crate::VcsSystem::Git
+        }
+        fn root(&self) -> &std::path::Path {
+            std::path::Path::new("/")
+        }
+        fn named_remotes(&self) -> Result<Vec<(String, Url)>, RemoteBuildError> {
+            Ok(Vec::new())
+        }
+        fn tags(&self) -> Result<Vec<String>, RemoteBuildError> {
+            Ok(vec!["0.9.0".to_string(), "1.0.0".to_string()])
+        }
+        fn head_ref(&self) -> Result<String, RemoteBuildError> {
+            Ok("abc".to_string())
+        }
+        fn is_dirty(&self) -> Result<bool, RemoteBuildError> {
+            Ok(false)
+        }
+        fn tag_ref(&self, tag: &str) -> Result<Option<String>, RemoteBuildError> {
+            Ok(match tag {
+                "0.9.0" => Some("def".to_string()),
+                "1.0.0" => Some("abc".to_string()),
+                _ => None,
+            })
+        }
+    }
+
+    #[test]
+    fn can_resume_release() {
+        assert!(!is_resumable(&Tagged, "1.1.0").unwrap());
+        assert!(is_resumable(&Tagged, "1.0.0").unwrap());
+        match is_resumable(&Tagged, "0.9.0") {
+            Err(RemoteBuildError::VcsError(msg)) => assert!(msg.contains("rather than HEAD")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! remote_url.rs
+//!
+//! Repository urls come from packalaka, from the remotes of a local working copy,
+//! and from the command line. Git happily accepts scp style remotes
+//! (eg `git@dd-git.d2.com:domains/animation/animtools.git`), which are not urls,
+//! so `parse_remote` converts them into their `ssh://` equivalent. The
+//! UrlNormalizer additionally applies the rewrite rules from the config, which map
+//! the urls we see locally onto urls which the build server is able to reach.
+use crate::{config::RemotesConfig, RemoteBuildError};
+use log::debug;
+use url::Url;
+
+/// Parse a repository url, accepting scp style remotes of the form
+/// `[user@]host:path`, which are converted to `ssh://[user@]host/path`.
+///
+/// # Parameters
+///
+/// * `raw` - The url or scp style remote
+///
+/// # Returns
+///
+/// Url or Error
+pub fn parse_remote(raw: &str) -> Result<Url, RemoteBuildError> {
+    let raw = raw.trim();
+    let converted = scp_to_ssh(raw);
+    Url::parse(converted.as_deref().unwrap_or(raw))
+        .map_err(|e| RemoteBuildError::RemoteUrlError(format!("unable to parse remote '{}': {}", raw, e)))
+}
+
+// convert an scp style remote into an ssh url. None is returned if the remote is
+// not scp style.
+fn scp_to_ssh(raw: &str) -> Option<String> {
+    if raw.contains("://") {
+        return None;
+    }
+    let colon = raw.find(':')?;
+    // the colon must come before any slash, otherwise this is a local path
+    if raw.find('/').map(|slash| slash < colon).unwrap_or(false) {
+        return None;
+    }
+    let (user_host, path) = (&raw[..colon], &raw[colon + 1..]);
+    let host = user_host.rsplit('@').next().unwrap_or(user_host);
+    // a single letter is a windows drive (eg C:\\foo)
+    if host.len() < 2 {
+        return None;
+    }
+    Some(format!("ssh://{}/{}", user_host, path.trim_start_matches('/')))
+}
+
+/// Rewrites repository urls according to the rules in the config. The rule with
+/// the longest matching prefix wins. Without any rules, urls are simply parsed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UrlNormalizer {
+    // (from, to) with the roots expanded and `from` in normal form
+    rules: Vec<(String, String)>,
+}
+
+impl UrlNormalizer {
+    /// Construct the UrlNormalizer from the remotes section of the config,
+    /// expanding `$GIT_ROOT` and `$SVN_ROOT`.
+    pub fn from_config(config: &RemotesConfig) -> Result<Self, RemoteBuildError> {
+        let mut rules = Vec::with_capacity(config.rewrites.len());
+        for rule in &config.rewrites {
+            let from = expand_roots(&rule.from, config)?;
+            let to = expand_roots(&rule.to, config)?;
+            // the replacement must result in a url
+            parse_remote(&format!("{}repo", to)).map_err(|e| {
+                RemoteBuildError::ConfigError(format!("rewrite of '{}' to '{}' is invalid: {}", rule.from, rule.to, e))
+            })?;
+            // compare against urls in the same form that we will be rewriting
+            let from = match parse_remote(&from) {
+                Ok(url) => url.as_str().to_string(),
+                Err(_) => from,
+            };
+            rules.push((from, to));
+        }
+        Ok(Self { rules })
+    }
+
+    /// Apply the rewrite rules to the supplied url. Any fragment is preserved.
+    pub fn rewrite(&self, url: &Url) -> Result<Url, RemoteBuildError> {
+        let raw = url.as_str();
+        let rule = self
+            .rules
+            .iter()
+            .filter(|(from, _)| raw.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len());
+        match rule {
+            Some((from, to)) => {
+                let rewritten = parse_remote(&format!("{}{}", to, &raw[from.len()..]))?;
+                debug!("rewrote {} to {}", url, rewritten);
+                Ok(rewritten)
+            }
+            None => Ok(url.clone()),
+        }
+    }
+
+    /// Parse the supplied url or scp style remote, and then rewrite it
+    pub fn normalize(&self, raw: &str) -> Result<Url, RemoteBuildError> {
+        self.rewrite(&parse_remote(raw)?)
+    }
+}
+
+// replace a leading $GIT_ROOT or $SVN_ROOT with the configured root, making sure
+// that the two are joined by a single slash
+fn expand_roots(value: &str, config: &RemotesConfig) -> Result<String, RemoteBuildError> {
+    for (var, root) in &[("$GIT_ROOT", &config.git_root), ("$SVN_ROOT", &config.svn_root)] {
+        if let Some(rest) = value.strip_prefix(var) {
+            return Ok(format!("{}{}", root.trim_end_matches('/'), rest));
+        }
+    }
+    if value.contains('$') {
+        return Err(RemoteBuildError::ConfigError(format!(
+            "'{}' refers to an unknown variable. Only $GIT_ROOT and $SVN_ROOT are supported",
+            value
+        )));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RewriteRule;
+
+    fn normalizer(rules: &[(&str, &str)]) -> UrlNormalizer {
+        UrlNormalizer::from_config(&RemotesConfig {
+            rewrites: rules
+                .iter()
+                .map(|(from, to)| RewriteRule {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+                .collect(),
+            ..RemotesConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn can_parse_scp_style_remotes() {
+        assert_eq!(
+            parse_remote("git@dd-git.d2.com:domains/x.git").unwrap().as_str(),
+            "ssh://git@dd-git.d2.com/domains/x.git"
+        );
+        assert_eq!(
+            parse_remote("dd-git.d2.com:/domains/x.git#tag=1.0.0").unwrap().as_str(),
+            "ssh://dd-git.d2.com/domains/x.git#tag=1.0.0"
+        );
+        assert_eq!(
+            parse_remote("ssh://git@dd-git.d2.com:2224/domains/x.git").unwrap().as_str(),
+            "ssh://git@dd-git.d2.com:2224/domains/x.git"
+        );
+        assert!(parse_remote("./domains/x.git").is_err());
+    }
+
+    #[test]
+    fn can_rewrite_urls() {
+        let normalizer = normalizer(&[
+            ("git@dd-git.d2.com:", "$GIT_ROOT/"),
+            ("ssh://git@dd-git-mirror.d2.com:2224/", "ssh://git@dd-git.d2.com:2224/"),
+            ("ssh://git@dd-git-mirror.d2.com:2224/legacy/", "$SVN_ROOT/legacy/"),
+            ("http://dd-git.d2.com/secure/", "ssh://git@dd-git.d2.com:2224/secure/"),
+        ]);
+        // ssh -> http
+        assert_eq!(
+            normalizer.normalize("git@dd-git.d2.com:domains/x.git").unwrap().as_str(),
+            "http://dd-git.d2.com/domains/x.git"
+        );
+        // http -> ssh, keeping the ref
+        assert_eq!(
+            normalizer.normalize("http://dd-git.d2.com/secure/x.git#tag=1.0.0").unwrap().as_str(),
+            "ssh://git@dd-git.d2.com:2224/secure/x.git#tag=1.0.0"
+        );
+        // host mapping, with the longest prefix winning
+        assert_eq!(
+            normalizer.normalize("ssh://git@dd-git-mirror.d2.com:2224/domains/x.git").unwrap().as_str(),
+            "ssh://git@dd-git.d2.com:2224/domains/x.git"
+        );
+        assert_eq!(
+            normalizer.normalize("ssh://git@dd-git-mirror.d2.com:2224/legacy/x").unwrap().as_str(),
+            "http://svnmaster/svn/software/legacy/x"
+        );
+        // untouched
+        assert_eq!(
+            normalizer.normalize("ssh://git@elsewhere.d2.com/x.git").unwrap().as_str(),
+            "ssh://git@elsewhere.d2.com/x.git"
+        );
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let config = RemotesConfig {
+            rewrites: vec![RewriteRule {
+                from: "http://dd-git.d2.com/".to_string(),
+                to: "$HOME/".to_string(),
+            }],
+            ..RemotesConfig::default()
+        };
+        assert!(UrlNormalizer::from_config(&config).is_err());
+        let config = RemotesConfig {
+            rewrites: vec![RewriteRule {
+                from: "http://dd-git.d2.com/".to_string(),
+                to: "/mnt/git/".to_string(),
+            }],
+            ..RemotesConfig::default()
+        };
+        assert!(UrlNormalizer::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! repo_ref.rs
+//!
+//! Packalaka reports the location of a tag as a link whose fragment identifies
+//! the ref within the repository (eg `ssh://...deferredpipeline.git#tag=3.5.0`).
+//! A RepoRef splits such a link into the repository url and the ref, so that the
+//! build server may be handed a clean repo url along with an explicit ref.
+use crate::{remote_url::parse_remote, svn_layout::SvnLayout, RemoteBuildError, VcsSystem};
+use std::{fmt, str::FromStr};
+use url::Url;
+
+/// The kind of ref a RepoRef points at
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Clone)]
+pub enum RefKind {
+    Tag,
+    Branch,
+    Commit,
+}
+
+impl FromStr for RefKind {
+    type Err = RemoteBuildError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "tag" => Ok(RefKind::Tag),
+            "branch" => Ok(RefKind::Branch),
+            "commit" | "rev" | "revision" => Ok(RefKind::Commit),
+            _ => Err(RemoteBuildError::RepoRefError(format!("unknown ref kind '{}'", value))),
+        }
+    }
+}
+
+impl fmt::Display for RefKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefKind::Tag => write!(f, "tag"),
+            RefKind::Branch => write!(f, "branch"),
+            RefKind::Commit => write!(f, "commit"),
+        }
+    }
+}
+
+/// A repository url, along with a ref within it
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Clone)]
+pub struct RepoRef {
+    /// The url of the repository, without a fragment
+    pub url: Url,
+    pub kind: RefKind,
+    pub name: String,
+}
+
+impl RepoRef {
+    /// New up a RepoRef. Any fragment on the url is discarded.
+    pub fn new<S: Into<String>>(mut url: Url, kind: RefKind, name: S) -> Self {
+        url.set_fragment(None);
+        Self {
+            url,
+            kind,
+            name: name.into(),
+        }
+    }
+
+    /// Parse a link of the form `<url>#<kind>=<name>` (eg `<url>#tag=3.5.0`). The
+    /// fragment is required. The url may be an scp style remote.
+    pub fn from_link(link: &str) -> Result<Self, RemoteBuildError> {
+        let url = parse_remote(link)?;
+        let fragment = url.fragment().ok_or_else(|| {
+            RemoteBuildError::RepoRefError(format!("{} does not identify a ref", link))
+        })?;
+        let mut pieces = fragment.splitn(2, '=');
+        let (kind, name) = match (pieces.next(), pieces.next()) {
+            (Some(kind), Some(name)) if !name.is_empty() => (kind.parse::<RefKind>()?, name.to_string()),
+            _ => {
+                return Err(RemoteBuildError::RepoRefError(format!(
+                    "unable to parse the ref '{}' of {}",
+                    fragment, link
+                )))
+            }
+        };
+        Ok(Self::new(url, kind, name))
+    }
+
+    /// Construct the RepoRef for the supplied tag from a packalaka link. Links without
+    /// a fragment (eg svn links) refer to the tag implicitly. A link whose fragment
+    /// names a different tag, or a branch or commit rather than a tag, is an error.
+    pub fn for_tag(link: &str, tag: &str) -> Result<Self, RemoteBuildError> {
+        let url = parse_remote(link)?;
+        if url.fragment().is_none() {
+            return Ok(Self::new(url, RefKind::Tag, tag));
+        }
+        let repo_ref = Self::from_link(link)?;
+        if repo_ref.kind != RefKind::Tag {
+            return Err(RemoteBuildError::RepoRefError(format!(
+                "{} refers to the {} {}, rather than the tag {}",
+                link, repo_ref.kind, repo_ref.name, tag
+            )));
+        }
+        if repo_ref.name != tag {
+            return Err(RemoteBuildError::TagMismatch {
+                requested: tag.to_string(),
+                linked: repo_ref.name,
+            });
+        }
+        Ok(repo_ref)
+    }
+
+    /// Retrieve the url of the repository
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Is the ref a tag?
+    pub fn is_tag(&self) -> bool {
+        self.kind == RefKind::Tag
+    }
+}
+
+impl fmt::Display for RepoRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}={}", self.url, self.kind, self.name)
+    }
+}
+
+/// Strip the fragment (eg #tag=1.2.3) and any trailing slash from a link
+pub fn repo_of(link: &str) -> &str {
+    link.split('#').next().unwrap_or(link).trim_end_matches('/')
+}
+
+/// Determine whether two links refer to the same repository, ignoring any fragment
+/// (eg #tag=1.2.3) and trailing slashes.
+pub fn same_repo(left: &str, right: &str) -> bool {
+    repo_of(left) == repo_of(right)
+}
+
+/// Determine whether two links refer to the same repository of the supplied
+/// version control system. Svn links are compared by project, so that
+/// `<project>/tags/<tag>` refers to the same repository as `<project>/trunk`,
+/// or `<project>` itself.
+pub fn same_repo_for(vcs: &VcsSystem, left: &str, right: &str) -> bool {
+    match vcs {
+        VcsSystem::Svn => svn_project_of(left) == svn_project_of(right),
+        _ => same_repo(left, right),
+    }
+}
+
+// the url of the svn project housing the link, or the link itself if it doesn't
+// follow the standard layout
+fn svn_project_of(link: &str) -> String {
+    let repo = repo_of(link);
+    Url::parse(repo)
+        .ok()
+        .and_then(|url| SvnLayout::from_url(&url, None).ok())
+        .map(|layout| layout.project.as_str().trim_end_matches('/').to_string())
+        .unwrap_or_else(|| repo.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK: &str = "ssh://git@dd-git.d2.com:2224/domains/lighting/deferredpipeline.git#tag=3.5.0.alpha2";
+
+    #[test]
+    fn can_parse_link() {
+        let repo_ref = RepoRef::from_link(LINK).unwrap();
+        assert_eq!(
+            repo_ref.url.as_str(),
+            "ssh://git@dd-git.d2.com:2224/domains/lighting/deferredpipeline.git"
+        );
+        assert_eq!(repo_ref.kind, RefKind::Tag);
+        assert_eq!(repo_ref.name, "3.5.0.alpha2");
+        assert_eq!(repo_ref.to_string(), LINK);
+
+        let repo_ref = RepoRef::from_link("http://dd-git.d2.com/foo.git#commit=abc123").unwrap();
+        assert_eq!(repo_ref.kind, RefKind::Commit);
+        assert!(RepoRef::from_link("http://dd-git.d2.com/foo.git").is_err());
+        assert!(RepoRef::from_link("http://dd-git.d2.com/foo.git#tag=").is_err());
+        assert!(RepoRef::from_link("http://dd-git.d2.com/foo.git#label=foo").is_err());
+    }
+
+    #[test]
+    fn can_construct_for_tag() {
+        let repo_ref = RepoRef::for_tag(LINK, "3.5.0.alpha2").unwrap();
+        assert!(repo_ref.is_tag());
+
+        let repo_ref = RepoRef::for_tag("http://dd-svn.d2.com/svn/software/packages/foo", "1.0.0").unwrap();
+        assert_eq!(repo_ref.name, "1.0.0");
+        assert_eq!(repo_ref.url.as_str(), "http://dd-svn.d2.com/svn/software/packages/foo");
+
+        // a branch or commit may move, so it can't stand in for the tag
+        match RepoRef::for_tag("http://dd-git.d2.com/foo.git#branch=3.5.0", "3.5.0") {
+            Err(RemoteBuildError::RepoRefError(msg)) => assert!(msg.contains("the branch 3.5.0")),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(RepoRef::for_tag("http://dd-git.d2.com/foo.git#commit=abc123", "3.5.0").is_err());
+
+        match RepoRef::for_tag(LINK, "3.5.0") {
+            Err(RemoteBuildError::TagMismatch { requested, linked }) => {
+                assert_eq!(requested, "3.5.0");
+                assert_eq!(linked, "3.5.0.alpha2");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_compare_repos() {
+        assert!(same_repo(LINK, "ssh://git@dd-git.d2.com:2224/domains/lighting/deferredpipeline.git/"));
+        assert!(!same_repo(LINK, "ssh://git@dd-git.d2.com:2224/domains/lighting/other.git"));
+
+        let project = "http://dd-svn.d2.com/svn/software/packages/foo";
+        let tag = "http://dd-svn.d2.com/svn/software/packages/foo/tags/1.2.3";
+        assert!(!same_repo(project, tag));
+        assert!(same_repo_for(&VcsSystem::Svn, project, tag));
+        assert!(same_repo_for(&VcsSystem::Svn, "http://dd-svn.d2.com/svn/software/packages/foo/trunk/", tag));
+        assert!(!same_repo_for(&VcsSystem::Svn, "http://dd-svn.d2.com/svn/software/packages/bar", tag));
+        assert!(!same_repo_for(&VcsSystem::Git, project, tag));
+    }
+}
@@ -0,0 +1,83 @@
+//! suggest.rs
+//!
+//! Suggest likely candidates for a misspelled name, drawn from a list of
+//! known names (eg the packages in the gpi, or the tags of a package).
+use strsim::damerau_levenshtein;
+
+/// The maximum number of suggestions offered
+pub const MAX_SUGGESTIONS: usize = 5;
+
+/// The minimum length of a name for it to be treated as a prefix of a candidate
+pub const MIN_PREFIX: usize = 3;
+
+/// Retrieve up to MAX_SUGGESTIONS candidates which are close to `name`, ordered
+/// from closest to furthest. A candidate is considered close when its edit distance
+/// from `name` is at most a third of the length of `name` (and at least 1), or when
+/// `name` is a prefix of it (eg `houdini` for `houdini_submission`).
+pub fn suggest<'a, I>(name: &str, candidates: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let len = name.chars().count();
+    let max_distance = std::cmp::max(1, len / 3);
+    let mut scored = candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (damerau_levenshtein(name, candidate), candidate))
+        .filter(|(distance, candidate)| {
+            *distance <= max_distance || (len >= MIN_PREFIX && candidate.starts_with(name))
+        })
+        .collect::<Vec<(usize, &str)>>();
+    scored.sort();
+    // the same candidate may be supplied more than once (eg tags reported by several sources)
+    scored.dedup();
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+/// Format suggestions for inclusion in an error message
+pub fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(" Did you mean: {}?", suggestions.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKAGES: &[&str] = &["animtools", "deferredpipeline", "houdini_submission", "packalaka"];
+
+    #[test]
+    fn can_suggest_close_names() {
+        assert_eq!(suggest("animtool", PACKAGES.iter().copied()), vec!["animtools"]);
+        assert_eq!(suggest("deferedpipeline", PACKAGES.iter().copied()), vec!["deferredpipeline"]);
+        assert_eq!(suggest("packlaaka", PACKAGES.iter().copied()), vec!["packalaka"]);
+    }
+
+    #[test]
+    fn can_suggest_by_prefix() {
+        assert_eq!(suggest("houdini", PACKAGES.iter().copied()), vec!["houdini_submission"]);
+        assert!(suggest("an", PACKAGES.iter().copied()).is_empty());
+        assert_eq!(suggest("3.5.2", vec!["3.5.1", "3.5.1", "4.0.0"]), vec!["3.5.1"]);
+    }
+
+    #[test]
+    fn does_not_suggest_distant_names() {
+        assert!(suggest("nuke", PACKAGES.iter().copied()).is_empty());
+    }
+
+    #[test]
+    fn can_format_suggestions() {
+        assert_eq!(did_you_mean(&[]), "");
+        assert_eq!(
+            did_you_mean(&["animtools".to_string(), "animtool2".to_string()]),
+            " Did you mean: animtools, animtool2?"
+        );
+    }
+}
@@ -1,90 +1,179 @@
-use crate::RemoteBuildError;
-use crate::{prelude::*, RemoteBuildError::ShellFnError};
-use shellfn::shell;
-use std::{env::current_dir, path::Path, path::PathBuf};
+use crate::{
+    prelude::*,
+    svn_layout::{self, SvnInfo, SvnLayout, SvnLocation},
+    RemoteBuildError, VcsSystem,
+};
 use log::debug;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-/// Query for the url of the svn server. This struct requires that
-/// the `svn` command be available on the shell to work. There are no
-/// subversion clients in Rust so we use the excellent `shellfn` crate
-/// to make quick work of this.
-pub struct Svn;
+/// A local svn working copy. This struct requires that the `svn` command be
+/// available on the shell to work, as there are no subversion clients in Rust.
+pub struct Svn {
+    root: PathBuf,
+}
 
 impl Vcs for Svn {
-    /// Test to see if the current working directory houses an svn repo.
+    /// Open the svn working copy housing `path`, which is identified by a `.svn`
+    /// directory in `path` or one of its parents.
     ///
     /// # Parameters
     ///
-    /// None
+    /// * `path` - The path to a directory within an svn working copy
     ///
     /// # Returns
     ///
-    /// A bool indicating whether or not the current working directory
-    /// is an svn repo or not.
-    fn is_cwd_repo() -> bool {
-        let cwd = current_dir().unwrap();
-        Svn::is_repo(cwd)
+    /// Svn or Error
+    fn open(path: &Path) -> Result<Self, RemoteBuildError> {
+        path.ancestors()
+            .find(|dir| dir.join(".svn").is_dir())
+            .map(|root| Svn {
+                root: root.to_path_buf(),
+            })
+            .ok_or_else(|| {
+                RemoteBuildError::VcsError(format!("{} is not within an svn working copy", path.display()))
+            })
     }
 
-    /// Test to see if the provied directory houses an svn repo.
-    ///
-    /// # Parameters
-    ///
-    /// * `pathbuf` - The path to a directory which we intend on testing
-    ///               to see if it houses an svn repository.
-    ///
-    /// # Returns
-    ///
-    /// Bool - indicates whether the repo exists
-    fn is_repo<I: Into<PathBuf>>(pathbuf: I) -> bool {
-        let mut pathbuf = pathbuf.into();
-        pathbuf.push(".svn");
-        pathbuf.exists()
+    fn system(&self) -> VcsSystem {
+        VcsSystem::Svn
     }
 
-    /// Get the svn url from the manifest
-    ///
-    /// # Parameters
-    ///
-    /// * None
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Get the url of the project, ie the directory housing trunk, branches and tags.
+    /// A working copy which doesn't follow the standard layout is an error.
     ///
     /// # Returns
     ///
-    /// A single element vector housing the Url to the svn server.
-    fn get_server_urls(path: &Path) -> Result<Vec<url::Url>, failure::Error> {
-        debug!("get_server_urls({:?})", path);
-        // should probably unwrap this into an error
-        let url_vec = _get_svn_url(path.to_str().unwrap_or("."))?;
-        if url_vec.len() == 0 {
-            Err(ShellFnError("unable to get svn url".to_string()).into())
-        } else {
-            Ok(vec![url::Url::parse(url_vec.as_str())?])
+    /// A single element vector housing the Url to the svn server, named `default`.
+    fn named_remotes(&self) -> Result<Vec<(String, url::Url)>, RemoteBuildError> {
+        Ok(vec![("default".to_string(), self.layout()?.project)])
+    }
+
+    /// The tags are the entries of the project's tags directory
+    fn tags(&self) -> Result<Vec<String>, RemoteBuildError> {
+        let listing = self.svn(&["ls", self.layout()?.tags_url().as_str()])?;
+        Ok(listing
+            .lines()
+            .filter_map(|x| x.trim().strip_suffix('/'))
+            .map(|x| x.to_string())
+            .collect())
+    }
+
+    /// The revision of the working copy
+    fn head_ref(&self) -> Result<String, RemoteBuildError> {
+        Ok(self.info()?.revision)
+    }
+
+    /// Tags are copies, so a working copy is only known to be at the tag when it has
+    /// the tag checked out (ie `<project>/tags/<tag>`)
+    fn tag_ref(&self, tag: &str) -> Result<Option<String>, RemoteBuildError> {
+        let info = self.info()?;
+        match SvnLayout::from_url(&info.url, Some(&info.root))?.location {
+            SvnLocation::Tag(ref name) if name == tag => Ok(Some(info.revision)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Unversioned files are not considered to make the working copy dirty
+    fn is_dirty(&self) -> Result<bool, RemoteBuildError> {
+        Ok(!self.svn(&["status", "-q"])?.trim().is_empty())
+    }
+
+    /// Tags live in the repository, at `<project>/tags/<tag>`
+    fn remote_tag_exists(&self, url: &url::Url, tag: &str) -> Result<Option<bool>, RemoteBuildError> {
+        let tag_url = remote_tag_url(url, tag);
+        match self.svn(&["ls", "--depth", "empty", tag_url.as_str()]) {
+            Ok(_) => Ok(Some(true)),
+            Err(RemoteBuildError::ShellFnError(ref msg)) if is_not_found(msg) => Ok(Some(false)),
+            Err(e) => Err(e),
         }
     }
 }
 
 impl Svn {
-    /// Get the svn url from the manifest
-    ///
-    /// # Parameters
-    ///
-    /// * `version`: &str representing an svn url version tag.
-    ///
-    /// # Returns
-    ///
-    /// A Url representing the svn server's url
-    pub fn get_url(path: &Path, version: &str) -> Result<url::Url, RemoteBuildError> {
-        debug!("get_url calling get_server_urls with {:?}", path);
-        let url = Svn::get_server_urls(path)?;
-        let url = &url[0];
-        let url = url::Url::parse(format!("{}/{}", url.as_str(), version).as_str())?;       
-        Ok(url)
+    /// Retrieve the information which `svn info` reports about the working copy
+    pub fn info(&self) -> Result<SvnInfo, RemoteBuildError> {
+        SvnInfo::from_xml(&self.svn(&["info", "--xml"])?)
+    }
+
+    /// Identify the layout of the working copy's url within its project
+    pub fn layout(&self) -> Result<SvnLayout, RemoteBuildError> {
+        let info = self.info()?;
+        debug!("svn url {} (root {})", info.url, info.root);
+        SvnLayout::from_url(&info.url, Some(&info.root))
+    }
+
+    // run svn from the root of the working copy, returning stdout
+    fn svn(&self, args: &[&str]) -> Result<String, RemoteBuildError> {
+        debug!("svn {}", args.join(" "));
+        let output = Command::new("svn").args(args).current_dir(&self.root).output()?;
+        if !output.status.success() {
+            return Err(RemoteBuildError::ShellFnError(format!(
+                "`svn {}` exited with {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 }
 
-#[shell]
-fn _get_svn_url(svn_base_path: &str) -> Result<String, failure::Error> {
-    r#"
-        cd $SVN_BASE_PATH && svn info --show-item url --no-newline | sed 's/trunk//'
-    "#
+// the url of the tag within the project housing `url`, which may point anywhere
+// within the project (eg trunk or a branch)
+fn remote_tag_url(url: &url::Url, tag: &str) -> url::Url {
+    let project = SvnLayout::from_url(url, None)
+        .map(|layout| layout.project)
+        .unwrap_or_else(|_| url.clone());
+    svn_layout::tag_url(&project, tag)
+}
+
+// does the error reported by svn indicate that the url does not exist?
+fn is_not_found(msg: &str) -> bool {
+    // W160013 (http), W170000 (svn) and E200009 (some targets don't exist)
+    ["W160013", "W170000", "E200009"].iter().any(|code| msg.contains(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn can_identify_missing_urls() {
+        assert!(is_not_found(
+            "`svn ls` exited with exit status: 1: svn: warning: W160013: URL 'http://svnmaster/svn/software/packages/foo/tags/9.9.9' non-existent in revision 1234"
+        ));
+        assert!(!is_not_found("`svn ls` exited with exit status: 1: svn: E170013: Unable to connect to a repository"));
+    }
+
+    #[test]
+    fn can_locate_remote_tags_within_project() {
+        let tag = "http://svnmaster/svn/software/packages/foo/tags/1.2.0";
+        for url in &[
+            "http://svnmaster/svn/software/packages/foo",
+            "http://svnmaster/svn/software/packages/foo/trunk",
+            "http://svnmaster/svn/software/packages/foo/branches/fix",
+        ] {
+            let url = url::Url::parse(url).unwrap();
+            assert_eq!(remote_tag_url(&url, "1.2.0").as_str(), tag);
+        }
+    }
+
+    #[test]
+    fn can_open_working_copy_from_subdirectory() {
+        let root = TempDir::new("svn");
+        std::fs::create_dir_all(root.join(".svn")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        assert_eq!(Svn::open(&root.join("src")).unwrap().root(), root.path());
+        let path = root.to_path_buf();
+        drop(root);
+        assert!(Svn::open(&path).is_err());
+    }
 }
@@ -0,0 +1,303 @@
+//! svn_layout.rs
+//!
+//! Subversion has no notion of tags or branches. By convention, each project
+//! houses a `trunk`, along with `branches/<name>` and `tags/<tag>` directories
+//! which are copies of it. SvnInfo is parsed from the output of `svn info --xml`,
+//! and SvnLayout models the conventional layout, from which the url of a tag is
+//! resolved as `<project>/tags/<tag>`. Urls which don't follow the convention are
+//! reported as nonstandard, rather than guessed at.
+use crate::RemoteBuildError;
+use std::{fmt, path::PathBuf};
+use url::Url;
+
+/// The information reported by `svn info --xml` about a working copy, or a url
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SvnInfo {
+    /// file or dir
+    pub kind: String,
+    pub url: Url,
+    /// The url relative to the repository root (eg `^/packages/foo/trunk`)
+    pub relative_url: Option<String>,
+    /// The url of the repository root
+    pub root: Url,
+    pub uuid: Option<String>,
+    /// The revision of the working copy, or url
+    pub revision: String,
+    /// The revision in which the url was last changed
+    pub last_changed_revision: Option<String>,
+    /// The root of the working copy, if this is a working copy
+    pub wcroot: Option<PathBuf>,
+}
+
+impl SvnInfo {
+    /// Parse the output of `svn info --xml`. Only the first entry is considered.
+    pub fn from_xml(xml: &str) -> Result<Self, RemoteBuildError> {
+        let entry = element(xml, "entry")
+            .ok_or_else(|| RemoteBuildError::VcsError("svn info reported no entries".to_string()))?;
+        let required = |name: &str| {
+            element_text(entry, name)
+                .ok_or_else(|| RemoteBuildError::VcsError(format!("svn info did not report the {}", name)))
+        };
+        Ok(Self {
+            kind: attribute(entry, "entry", "kind").unwrap_or_else(|| "dir".to_string()),
+            url: Url::parse(&required("url")?)?,
+            relative_url: element_text(entry, "relative-url"),
+            root: Url::parse(&required("root")?)?,
+            uuid: element_text(entry, "uuid"),
+            revision: attribute(entry, "entry", "revision")
+                .ok_or_else(|| RemoteBuildError::VcsError("svn info did not report the revision".to_string()))?,
+            last_changed_revision: attribute(entry, "commit", "revision"),
+            wcroot: element_text(entry, "wcroot-abspath").map(PathBuf::from),
+        })
+    }
+}
+
+/// Where within a project a url lies
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SvnLocation {
+    Trunk,
+    Branch(String),
+    Tag(String),
+}
+
+impl fmt::Display for SvnLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvnLocation::Trunk => write!(f, "trunk"),
+            SvnLocation::Branch(name) => write!(f, "branches/{}", name),
+            SvnLocation::Tag(name) => write!(f, "tags/{}", name),
+        }
+    }
+}
+
+/// A url within a project following the standard trunk / branches / tags layout
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SvnLayout {
+    /// The url of the project, which houses trunk, branches and tags
+    pub project: Url,
+    pub location: SvnLocation,
+    /// The path below the trunk, branch or tag, if any (eg `src/python`)
+    pub subpath: String,
+}
+
+impl SvnLayout {
+    /// Identify the layout of the url. Only the portion of the url below the
+    /// repository root, if supplied, is considered. The first `trunk`, or
+    /// `branches/<name>` or `tags/<name>`, identifies the project.
+    ///
+    /// # Parameters
+    ///
+    /// * `url` - The url of a working copy, or a path in the repository
+    /// * `root` - The url of the repository root, as reported by `svn info`
+    ///
+    /// # Returns
+    ///
+    /// SvnLayout, or an SvnLayoutError if the url doesn't follow the standard layout
+    pub fn from_url(url: &Url, root: Option<&Url>) -> Result<Self, RemoteBuildError> {
+        let path = url.path().trim_end_matches('/');
+        let skip = match root {
+            Some(root) if path.starts_with(root.path().trim_end_matches('/')) => {
+                root.path().trim_end_matches('/').split('/').count()
+            }
+            _ => 1, // the empty segment preceding the leading slash
+        };
+        let segments: Vec<&str> = path.split('/').collect();
+        for idx in skip..segments.len() {
+            let (location, rest) = match (segments[idx], segments.get(idx + 1)) {
+                ("trunk", _) => (SvnLocation::Trunk, idx + 1),
+                ("branches", Some(name)) if !name.is_empty() => (SvnLocation::Branch(name.to_string()), idx + 2),
+                ("tags", Some(name)) if !name.is_empty() => (SvnLocation::Tag(name.to_string()), idx + 2),
+                _ => continue,
+            };
+            if idx == skip {
+                return Err(RemoteBuildError::SvnLayoutError(format!(
+                    "{} is at the root of the repository, rather than within a project",
+                    url
+                )));
+            }
+            let mut project = url.clone();
+            project.set_path(&segments[..idx].join("/"));
+            project.set_query(None);
+            project.set_fragment(None);
+            return Ok(Self {
+                project,
+                location,
+                subpath: segments[rest.min(segments.len())..].join("/"),
+            });
+        }
+        Err(RemoteBuildError::SvnLayoutError(format!(
+            "{} is not within trunk, branches/<branch> or tags/<tag>",
+            url
+        )))
+    }
+
+    /// The url of the project's tags directory
+    pub fn tags_url(&self) -> Url {
+        join(&self.project, "tags")
+    }
+
+    /// The url of the tag, ie `<project>/tags/<tag>`
+    pub fn tag_url(&self, tag: &str) -> Url {
+        tag_url(&self.project, tag)
+    }
+}
+
+/// Resolve the url of the tag within the project, ie `<project>/tags/<tag>`
+pub fn tag_url(project: &Url, tag: &str) -> Url {
+    join(&join(project, "tags"), tag)
+}
+
+// append a segment to the url's path
+fn join(url: &Url, segment: &str) -> Url {
+    let mut joined = url.clone();
+    let path = format!("{}/{}", url.path().trim_end_matches('/'), segment);
+    joined.set_path(&path);
+    joined
+}
+
+// Retrieve the first element with the supplied name, including its tags
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = find_tag(xml, name)?;
+    let close = format!("</{}>", name);
+    let end = xml[start..].find(&close)? + start + close.len();
+    Some(&xml[start..end])
+}
+
+// Retrieve the unescaped text of the first element with the supplied name
+fn element_text(xml: &str, name: &str) -> Option<String> {
+    let element = element(xml, name)?;
+    let text = &element[element.find('>')? + 1..element.rfind("</")?];
+    Some(unescape(text.trim()))
+}
+
+// Retrieve the value of an attribute of the first element with the supplied name
+fn attribute(xml: &str, name: &str, attribute: &str) -> Option<String> {
+    let start = find_tag(xml, name)?;
+    let tag = &xml[start..start + xml[start..].find('>')?];
+    let needle = format!("{}=\"", attribute);
+    let mut offset = 0;
+    // make sure that we don't match the tail of a longer attribute name
+    while let Some(idx) = tag[offset..].find(&needle) {
+        let idx = offset + idx;
+        if tag[..idx].ends_with(char::is_whitespace) {
+            let value = &tag[idx + needle.len()..];
+            return Some(unescape(&value[..value.find('"')?]));
+        }
+        offset = idx + needle.len();
+    }
+    None
+}
+
+// find the start of the first opening tag with the supplied name
+fn find_tag(xml: &str, name: &str) -> Option<usize> {
+    let open = format!("<{}", name);
+    let mut offset = 0;
+    while let Some(idx) = xml[offset..].find(&open) {
+        let idx = offset + idx;
+        let next = xml[idx + open.len()..].chars().next();
+        if next.map(|c| c == '>' || c.is_whitespace()).unwrap_or(false) {
+            return Some(idx);
+        }
+        offset = idx + open.len();
+    }
+    None
+}
+
+// replace the predefined xml entities
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<info>
+<entry
+   kind="dir"
+   path="."
+   revision="1234">
+<url>http://svnmaster/svn/software/packages/foo/trunk</url>
+<relative-url>^/packages/foo/trunk</relative-url>
+<repository>
+<root>http://svnmaster/svn/software</root>
+<uuid>f3b9c2a4-1111-2222-3333-444455556666</uuid>
+</repository>
+<wc-info>
+<wcroot-abspath>/home/someone/src/foo</wcroot-abspath>
+<schedule>normal</schedule>
+<depth>infinity</depth>
+</wc-info>
+<commit
+   revision="1200">
+<author>someone</author>
+<date>2020-01-01T10:00:00.000000Z</date>
+</commit>
+</entry>
+</info>
+"#;
+
+    fn layout(url: &str) -> Result<SvnLayout, RemoteBuildError> {
+        let root = Url::parse("http://svnmaster/svn/software").unwrap();
+        SvnLayout::from_url(&Url::parse(url).unwrap(), Some(&root))
+    }
+
+    #[test]
+    fn can_parse_info() {
+        let info = SvnInfo::from_xml(INFO).unwrap();
+        assert_eq!(info.kind, "dir");
+        assert_eq!(info.url.as_str(), "http://svnmaster/svn/software/packages/foo/trunk");
+        assert_eq!(info.relative_url.as_deref(), Some("^/packages/foo/trunk"));
+        assert_eq!(info.root.as_str(), "http://svnmaster/svn/software");
+        assert_eq!(info.revision, "1234");
+        assert_eq!(info.last_changed_revision.as_deref(), Some("1200"));
+        assert_eq!(info.wcroot, Some(PathBuf::from("/home/someone/src/foo")));
+
+        let escaped = INFO.replace("packages/foo/trunk</url>", "packages/foo&amp;bar/trunk</url>");
+        assert_eq!(
+            SvnInfo::from_xml(&escaped).unwrap().url.as_str(),
+            "http://svnmaster/svn/software/packages/foo&bar/trunk"
+        );
+        assert!(SvnInfo::from_xml("<info></info>").is_err());
+    }
+
+    #[test]
+    fn can_identify_layout() {
+        let trunk = layout("http://svnmaster/svn/software/packages/foo/trunk").unwrap();
+        assert_eq!(trunk.project.as_str(), "http://svnmaster/svn/software/packages/foo");
+        assert_eq!(trunk.location, SvnLocation::Trunk);
+        assert_eq!(trunk.subpath, "");
+        assert_eq!(
+            trunk.tag_url("1.2.0").as_str(),
+            "http://svnmaster/svn/software/packages/foo/tags/1.2.0"
+        );
+
+        let subdir = layout("http://svnmaster/svn/software/packages/foo/trunk/src/python").unwrap();
+        assert_eq!(subdir.project, trunk.project);
+        assert_eq!(subdir.subpath, "src/python");
+
+        let branch = layout("http://svnmaster/svn/software/packages/foo/branches/fix-trunk/").unwrap();
+        assert_eq!(branch.project, trunk.project);
+        assert_eq!(branch.location, SvnLocation::Branch("fix-trunk".to_string()));
+
+        let tag = layout("http://svnmaster/svn/software/packages/foo/tags/1.0.0/src").unwrap();
+        assert_eq!(tag.location, SvnLocation::Tag("1.0.0".to_string()));
+        assert_eq!(tag.tags_url().as_str(), "http://svnmaster/svn/software/packages/foo/tags");
+    }
+
+    #[test]
+    fn nonstandard_layouts_are_reported() {
+        // trunk must be a path segment of its own
+        match layout("http://svnmaster/svn/software/packages/trunkated") {
+            Err(RemoteBuildError::SvnLayoutError(msg)) => assert!(msg.contains("not within trunk")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(layout("http://svnmaster/svn/software/packages/foo/tags").is_err());
+        assert!(layout("http://svnmaster/svn/software/trunk").is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! test_utils.rs
+//!
+//! Fixtures shared by the unit tests.
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A scratch directory, unique to the test and the process, which is removed
+/// when dropped - including when the test panics.
+#[derive(Debug)]
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty `pkg-build-remote-<name>-<pid>` directory under the system
+    /// temp dir, replacing any left behind by an earlier run.
+    pub(crate) fn new(name: &str) -> Self {
+        let mut root = std::env::temp_dir();
+        root.push(format!("pkg-build-remote-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        TempDir(root)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
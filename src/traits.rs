@@ -1,11 +1,46 @@
-use failure;
-use std::path::{Path, PathBuf};
+use crate::{RemoteBuildError, VcsSystem};
+use std::path::Path;
 
+/// A local working copy, managed by one of the supported version control systems.
+/// Instances are opened directly (eg `Git::open(path)`), or handed out boxed by
+/// `VcsSystem::open` and `VcsSystem::detect`.
 pub trait Vcs {
-    /// is the current working directory a repository?
-    fn is_cwd_repo() -> bool;
-    /// is the supplied path buffer a repository?
-    fn is_repo<I: Into<PathBuf>>(pathbuf: I) -> bool;
-    /// retrieve server url(s)
-    fn get_server_urls(path: &Path) -> Result<Vec<url::Url>, failure::Error>;
+    /// open the working copy housing the supplied path
+    fn open(path: &Path) -> Result<Self, RemoteBuildError>
+    where
+        Self: Sized;
+    /// the version control system managing the working copy
+    fn system(&self) -> VcsSystem;
+    /// the root of the working copy
+    fn root(&self) -> &Path;
+    /// retrieve the remote repositories as (name, url) pairs, in the order in which
+    /// the version control system reports them
+    fn named_remotes(&self) -> Result<Vec<(String, url::Url)>, RemoteBuildError>;
+    /// retrieve the urls of the remote repositories
+    fn remotes(&self) -> Result<Vec<url::Url>, RemoteBuildError> {
+        Ok(self.named_remotes()?.into_iter().map(|(_, url)| url).collect())
+    }
+    /// retrieve the names of the tags
+    fn tags(&self) -> Result<Vec<String>, RemoteBuildError>;
+    /// retrieve the revision which the working copy has checked out (eg a git
+    /// commit id, or an svn revision number)
+    fn head_ref(&self) -> Result<String, RemoteBuildError>;
+    /// does the working copy have uncommitted changes?
+    fn is_dirty(&self) -> Result<bool, RemoteBuildError>;
+    /// retrieve the revision which the supplied tag identifies, in the same form as
+    /// `head_ref`. None is returned if the tag is unknown locally, or the version
+    /// control system is unable to tell.
+    fn tag_ref(&self, _tag: &str) -> Result<Option<String>, RemoteBuildError> {
+        Ok(None)
+    }
+    /// does the supplied tag exist?
+    fn tag_exists(&self, tag: &str) -> Result<bool, RemoteBuildError> {
+        Ok(self.tags()?.iter().any(|x| x == tag))
+    }
+    /// does the supplied tag exist in the remote repository at `url`? None is
+    /// returned if the version control system is unable to tell, in which case
+    /// packalaka may be asked instead.
+    fn remote_tag_exists(&self, _url: &url::Url, _tag: &str) -> Result<Option<bool>, RemoteBuildError> {
+        Ok(None)
+    }
 }
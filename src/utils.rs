@@ -1,20 +1,28 @@
 
 use log::{debug};
 use crate::{
+    build_plan::BuildPlan,
+    gpi::Record,
+    from_manifest::checks::{CheckStatus, WorkingCopyChecks},
+    repo_ref::RepoRef,
+    remote_url::UrlNormalizer,
+    source_selector::SelectedSource,
+    config::Constraint,
     BuildRequest, 
     BuildServer, 
     PackageBuildRequest,
-    //Minifest, 
+    Minifest, 
     RemoteBuildError,
-    VcsSystem, Platform, Flavors
+    VcsSystem,
+    Platform, Flavors, FlavorSpec,
 };
-use prettytable::{cell, format, row, table};
+use prettytable::{cell, format, row, table, Table};
 use std::{
+    collections::BTreeMap,
     io::{stdin, stdout, Write},
-    //path::Path,
+    path::Path,
 };
 
-/* REMOVED LOCAL MODE
 // get the minifest from the path, unless both the name and tag are passed in as Some. Then
 // in that case, build the minifest out of them
 pub fn get_minifest(
@@ -27,15 +35,17 @@ pub fn get_minifest(
         let tag = tag.as_ref().unwrap();
         Ok(Minifest::new(name.clone(), tag.clone()))
     } else {
-        Minifest::from_disk(Some(&project_path))
+        Minifest::from_disk(Some(project_path))
     }
 }
-*/
 
 
 /// Holds the build variants, representing the different potential routes
 /// to trigger a build.
+// requests are built one at a time, so the size difference between the variants
+// doesn't warrant boxing (and changing the public api)
 #[derive(Debug, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)]
 pub enum UserBuildRequest {
     Distribution(BuildRequest),
     Package(PackageBuildRequest)
@@ -52,39 +62,201 @@ impl UserBuildRequest {
     }
 }
 
-// Trigger a build on the given build server, with the project identified
-// using the supplied parameters. Of course, if dry_run is true, then simply
-// pretend to do a build.
-pub fn request_build_for(
-    build_server: &BuildServer,
+// Describe the record's active source for presentation to the user
+fn describe_active_source(record: &Record) -> String {
+    match record.active_source() {
+        Some(source) => format!("{} ({})", source.link(), source.uses().to_string()),
+        None => "None".to_string(),
+    }
+}
+
+/// Present the user with a yes / no question, returning true if they answer yes.
+pub fn confirm(question: &str) -> bool {
+    print!("{} (y/n) ", question);
+    stdout().flush().expect("unable to flush stdout");
+    let mut result = String::new();
+    stdin()
+        .read_line(&mut result)
+        .expect("Failed to read line");
+    let result = result.trim().to_lowercase();
+    result == "y" || result == "yes"
+}
+
+/// Offer the user a numbered list of choices, returning the one picked, if any.
+pub fn pick(question: &str, choices: &[String]) -> Option<String> {
+    if choices.is_empty() {
+        return None;
+    }
+    for (idx, choice) in choices.iter().enumerate() {
+        println!("  {}) {}", idx + 1, choice);
+    }
+    print!("{} (1-{}, or n) ", question, choices.len());
+    stdout().flush().expect("unable to flush stdout");
+    let mut result = String::new();
+    stdin()
+        .read_line(&mut result)
+        .expect("Failed to read line");
+    result
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|idx| *idx >= 1 && *idx <= choices.len())
+        .map(|idx| choices[idx - 1].clone())
+}
+
+// Group flavor names by their host application, preserving the order of the 
+// flavors within each group. Flavors without a host application are grouped
+// under None.
+fn group_flavors_by_host<'a>(flavors: &[&'a str]) -> BTreeMap<Option<String>, Vec<&'a str>> {
+    let mut groups: BTreeMap<Option<String>, Vec<&'a str>> = BTreeMap::new();
+    for flav in flavors {
+        let spec = FlavorSpec::from_flavor(flav);
+        groups
+            .entry(spec.host().map(|x| x.to_string()))
+            .or_default()
+            .push(flav);
+    }
+    groups
+}
+
+// Construct the BuildPlan for the supplied comma separated platforms and flavors,
+// skipping any combinations ruled out by the constraints.
+pub fn build_plan_for(
     name: &str,
     version: &str,
-    vcs_project_url: &url::Url,
-    vcs: &VcsSystem,
     platforms: &str,
     flavors: &str,
-    dry_run: bool,
-    verbose: bool,
-    prompt: bool,
-) -> Result<(), RemoteBuildError> {
+    constraints: &[Constraint],
+) -> Result<BuildPlan, RemoteBuildError> {
     let platforms = Platform::parse_platforms(platforms);
     let flavors = Flavors::parse_flavors(flavors);
 
-    debug!("{:?}", vcs_project_url);
+    let mut plan = BuildPlan::new(name, version, &platforms, &flavors);
+    plan.apply_constraints(constraints)?;
+    Ok(plan)
+}
+
+/// A BuildPlan, along with the source it is to be built from
+pub struct SourcedPlan<'a> {
+    pub plan: BuildPlan,
+    pub source: SelectedSource<'a>,
+}
 
-    if dry_run || verbose || prompt {
-        let platform_str: Vec<String> = platforms.iter().map(|x| x.to_string()).collect();
+/// A ref to build, which has been read from a working copy, along with the
+/// results of checking the working copy against it
+pub struct LocalSource<'a> {
+    pub repo_ref: RepoRef,
+    pub vcs: &'a VcsSystem,
+    pub checks: WorkingCopyChecks,
+}
+
+// The outcome of submitting the build requests for a single tag
+struct TagReport {
+    tag: String,
+    submitted: usize,
+    skipped: usize,
+    error: Option<RemoteBuildError>,
+}
+
+// Generate a table summarizing the submission of each tag
+fn report_table(reports: &[TagReport], dry_run: bool) -> Table {
+    let mut table = table!(
+        [FYbH4c -> "Build Report"],
+        [FYb -> "Tag", FYb -> "Submitted", FYb -> "Skipped", FYb -> "Status"]
+    );
+    for report in reports {
+        let submitted = report.submitted.to_string();
+        let skipped = report.skipped.to_string();
+        match report.error {
+            Some(ref e) => table.add_row(row![
+                Fw -> report.tag.as_str(), Fw -> submitted.as_str(), Fw -> skipped.as_str(), Frb -> e.to_string().as_str()
+            ]),
+            None => table.add_row(row![
+                Fw -> report.tag.as_str(), Fw -> submitted.as_str(), Fw -> skipped.as_str(), 
+                Fgb -> if dry_run {"ok (dry run)"} else {"ok"}
+            ]),
+        };
+    }
+    table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+    table
+}
+
+// Present the report when more than one tag was submitted, and return the first
+// error encountered, if any.
+fn finish_reports(reports: Vec<TagReport>, dry_run: bool) -> Result<(), RemoteBuildError> {
+    if reports.len() > 1 {
+        report_table(&reports, dry_run).printstd();
+        println!();
+        let failed = reports.iter().filter(|x| x.error.is_some()).count();
+        if failed > 0 {
+            return Err(RemoteBuildError::FailureError(format!(
+                "{} of {} tags failed to submit", failed, reports.len()
+            )));
+        }
+        return Ok(());
+    }
+    match reports.into_iter().next().and_then(|x| x.error) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Construct the BuildRequests for the entries in the plan which have not been
+// skipped, presenting the plan to the user if requested. The repository url is
+// rewritten into a form which the build server can reach.
+fn build_requests_for(
+    build_server: &BuildServer,
+    sourced: &SourcedPlan,
+    record: &Record,
+    normalizer: &UrlNormalizer,
+    present: bool,
+) -> Result<Vec<BuildRequest>, RemoteBuildError> {
+    let plan = &sourced.plan;
+    let source = &sourced.source;
+    let name = plan.project.as_str();
+    let version = plan.version.as_str();
+    let flavors = plan.flavors();
+    // validate the link against the tag up front, rather than once per entry
+    let mut repo_ref = source.tag.repo_ref(version)?;
+    repo_ref.url = normalizer.rewrite(&repo_ref.url)?;
+    let vcs = &source.tag.uses;
+    // the gpi knows whether the source requires submodules, or lives in a subdirectory
+    let gpi_source = record.source_for(&source.tag.link);
+    let init_submodules = gpi_source.map(|x| x.init_submodules()).unwrap_or(false);
+    let subdirectory = gpi_source.and_then(|x| x.subdirectory());
+
+    debug!("{:?}", repo_ref);
+
+    if present {
+        let platform_str: Vec<String> = plan.platforms().iter().map(|x| x.to_string()).collect();
         let platform_str = platform_str.join(" , ");
 
         let mut table = table!(
             [FYbH2c -> "Remote Build Request Information"],
             [FYb -> "Route",     Fwb -> build_server.request_route().ok_or(RemoteBuildError::EmptyError("unable to unwrap request_route".into()))?],
             [FYb -> "Project",   Fwb ->  name],
-            [FYb -> "VCS Tag",   Fwb -> version],
-            [FYb -> "Flavors",   Fwb -> flavors.join(" , ").as_str()],
-            [FYb -> "VCS Repo",  Fwb -> vcs_project_url.as_str()],
-            [FYb -> "Platforms", Fwb -> platform_str.as_str()]
+            [FYb -> "Package Type", Fwb -> record.pkg_type().as_ref()],
+            [FYb -> "Active Source", Fwb -> describe_active_source(record).as_str()],
+            [FYb -> "VCS Tag",   Fwb -> version]
         );
+        // group the flavors by host application
+        for (host, flavs) in group_flavors_by_host(&flavors) {
+            let label = match host {
+                Some(host) => format!("Flavors ({})", host),
+                None => "Flavors".to_string(),
+            };
+            table.add_row(row![FYb -> label.as_str(), Fwb -> flavs.join(" , ").as_str()]);
+        }
+        table.add_row(row![FYb -> "Source",    Fwb -> source.to_string().as_str()]);
+        table.add_row(row![FYb -> "VCS Repo",  Fwb -> repo_ref.url().as_str()]);
+        table.add_row(row![FYb -> "VCS Ref",   Fwb -> format!("{} {}", repo_ref.kind, repo_ref.name).as_str()]);
+        if let Some(subdirectory) = subdirectory {
+            table.add_row(row![FYb -> "Subdirectory", Fwb -> subdirectory]);
+        }
+        if init_submodules {
+            table.add_row(row![FYb -> "Submodules", Fwb -> "initialized"]);
+        }
+        table.add_row(row![FYb -> "Platforms", Fwb -> platform_str.as_str()]);
         // FORMAT_CLEAN
         // FORMAT_NO_COLSEP
         // FORMAT_BORDERS_ONLY
@@ -92,43 +264,86 @@ pub fn request_build_for(
         println!("");
         table.printstd();
         println!("");
+        plan.table().printstd();
+        println!("");
     }
-    if prompt {
-        print!("Do you wish to submit a build request? (y/n) ");
-        stdout().flush().ok().expect("unable to flush stdout");
-        let reader = stdin();
-        let mut result = String::new();
-        let _ = reader
-            .read_line(&mut result)
-            .ok()
-            .expect("Failed to read line");
-        result = result.to_lowercase();
-        if result != "y" && result != "yes" {
-            println!("User cancelled build request: {}", result);
-            std::process::exit(0);
-        }
-    }
-    for platform in platforms {
-        let build_reqs = BuildRequest::build_requests(
+
+    let mut requests = Vec::new();
+    for entry in plan.to_submit() {
+        let br = BuildRequest::new(
             name,
             version,
-            vcs_project_url.as_str(),
+            entry.flavor.as_str(),
+            repo_ref.to_string().as_str(),
             vcs,
-            &platform,
-            &flavors,
-        )?;
-        for br in build_reqs {
-            debug!("{:?}", br);
-            let _results = build_server.request_build(UserBuildRequest::Distribution(br), verbose, dry_run)?;
+            &entry.platform,
+        )?
+        .with_submodules(init_submodules)
+        .with_subdirectory(subdirectory);
+        debug!("{:?}", br);
+        requests.push(br);
+    }
+    Ok(requests)
+}
+
+// Trigger a build on the given build server for each of the entries in each of 
+// the plans which have not been skipped. All of the plans are presented, and 
+// confirmed, up front. Of course, if dry_run is true, then simply pretend to do 
+// a build.
+pub fn request_builds_for(
+    build_server: &BuildServer,
+    plans: &[SourcedPlan],
+    record: &Record,
+    normalizer: &UrlNormalizer,
+    dry_run: bool,
+    verbose: bool,
+    prompt: bool,
+) -> Result<(), RemoteBuildError> {
+    let mut requests = Vec::with_capacity(plans.len());
+    for sourced in plans {
+        requests.push(build_requests_for(build_server, sourced, record, normalizer, dry_run || verbose || prompt)?);
+    }
+    let total = requests.iter().map(|x| x.len()).sum::<usize>();
+    if total == 0 {
+        println!("Nothing to submit. All platform / flavor combinations have been skipped.");
+        return Ok(());
+    }
+    let question = if plans.len() == 1 {
+        "Do you wish to submit a build request?".to_string()
+    } else {
+        format!("Do you wish to submit {} build requests for {} tags?", total, plans.len())
+    };
+    if prompt && !confirm(&question) {
+        println!("User cancelled build request");
+        std::process::exit(0);
+    }
+    let mut reports = Vec::with_capacity(plans.len());
+    for (sourced, requests) in plans.iter().zip(requests) {
+        let mut report = TagReport {
+            tag: sourced.plan.version.clone(),
+            submitted: 0,
+            skipped: sourced.plan.skipped().count(),
+            error: None,
+        };
+        for br in requests {
+            match build_server.request_build(UserBuildRequest::Distribution(br), verbose, dry_run) {
+                Ok(_) => report.submitted += 1,
+                Err(e) => {
+                    report.error = Some(e.into());
+                    break;
+                }
+            }
         }
+        reports.push(report);
     }
-    Ok(())
+    finish_reports(reports, dry_run)
 }
 
- pub fn request_package_build_for(
+ pub fn request_package_builds_for(
             build_server: &BuildServer,
             name: &str,
-            tag: &str,
+            tags: &[String],
+            record: &Record,
             dry_run: bool,
             verbose: bool,
             prompt: bool
@@ -138,36 +353,139 @@ pub fn request_build_for(
                 
 
                 let mut table = table!(
-                    [FYbH2c -> "Remote Package Build Request Information"],
-                    [FYb -> "Route",     Fwb -> build_server.request_build_route(name, tag).ok_or(RemoteBuildError::EmptyError("unable to unwrap request_route".into()))?],
-                    [FYb -> "Project",   Fwb ->  name],
-                    [FYb -> "VCS Tag",   Fwb -> tag]
+                    [FYbH2c -> "Remote Package Build Request Information"]
                 );
+                for tag in tags {
+                    let label = if tags.len() == 1 {"Route".to_string()} else {format!("Route ({})", tag)};
+                    table.add_row(row![FYb -> label.as_str(), Fwb -> build_server.request_build_route(name, tag).ok_or(RemoteBuildError::EmptyError("unable to unwrap request_route".into()))?]);
+                }
+                table.add_row(row![FYb -> "Project",   Fwb ->  name]);
+                table.add_row(row![FYb -> "Package Type", Fwb -> record.pkg_type().as_ref()]);
+                table.add_row(row![FYb -> "Active Source", Fwb -> describe_active_source(record).as_str()]);
+                table.add_row(row![FYb -> "VCS Tag",   Fwb -> tags.join(" , ").as_str()]);
             
                 table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
                 println!("");
                 table.printstd();
                 println!("");
             }
-            if prompt {
-                print!("Do you wish to submit a pcakage build request? (y/n) ");
-                stdout().flush().ok().expect("unable to flush stdout");
-                let reader = stdin();
-                let mut result = String::new();
-                let _ = reader
-                    .read_line(&mut result)
-                    .ok()
-                    .expect("Failed to read line");
-                result = result.to_lowercase();
-                if result != "y" && result != "yes" {
-                    println!("User cancelled build request: {}", result);
-                    std::process::exit(0);
-                }
+            let question = if tags.len() == 1 {
+                "Do you wish to submit a package build request?".to_string()
+            } else {
+                format!("Do you wish to submit {} package build requests?", tags.len())
+            };
+            if prompt && !confirm(&question) {
+                println!("User cancelled build request");
+                std::process::exit(0);
             }
-            build_server.request_build(
-                UserBuildRequest::Package(PackageBuildRequest::new(name, tag)), 
-                verbose, 
-                dry_run
-            )?;
-            Ok(())
-        }
\ No newline at end of file
+            let mut reports = Vec::with_capacity(tags.len());
+            for tag in tags {
+                let result = build_server.request_build(
+                    UserBuildRequest::Package(PackageBuildRequest::new(name, tag.as_str())), 
+                    verbose, 
+                    dry_run
+                );
+                reports.push(TagReport {
+                    tag: tag.clone(),
+                    submitted: if result.is_ok() {1} else {0},
+                    skipped: 0,
+                    error: result.err().map(|e| e.into()),
+                });
+            }
+            finish_reports(reports, dry_run)
+        }
+
+/// Trigger a build on the given build server for each of the entries in the plan 
+/// which have not been skipped, using a repository identified locally, rather than 
+/// via the gpi. Failed working copy checks are presented along with the plan, and
+/// then refuse the build. Of course, if dry_run is true, then simply pretend to do
+/// a build.
+pub fn request_local_build_for(
+    build_server: &BuildServer,
+    plan: &BuildPlan,
+    source: &LocalSource,
+    dry_run: bool,
+    verbose: bool,
+    prompt: bool,
+) -> Result<(), RemoteBuildError> {
+    let name = plan.project.as_str();
+    let version = plan.version.as_str();
+    let repo_ref = &source.repo_ref;
+    let vcs = source.vcs;
+
+    if dry_run || verbose || prompt {
+        let platform_str: Vec<String> = plan.platforms().iter().map(|x| x.to_string()).collect();
+        let platform_str = platform_str.join(" , ");
+
+        let mut table = table!(
+            [FYbH2c -> "Remote Build Request Information"],
+            [FYb -> "Route",     Fwb -> build_server.request_route().ok_or(RemoteBuildError::EmptyError("unable to unwrap request_route".into()))?],
+            [FYb -> "Project",   Fwb ->  name],
+            [FYb -> "VCS Tag",   Fwb -> version]
+        );
+        for (host, flavs) in group_flavors_by_host(&plan.flavors()) {
+            let label = match host {
+                Some(host) => format!("Flavors ({})", host),
+                None => "Flavors".to_string(),
+            };
+            table.add_row(row![FYb -> label.as_str(), Fwb -> flavs.join(" , ").as_str()]);
+        }
+        table.add_row(row![FYb -> "VCS",       Fwb -> vcs.to_string().as_str()]);
+        table.add_row(row![FYb -> "VCS Repo",  Fwb -> repo_ref.url().as_str()]);
+        table.add_row(row![FYb -> "VCS Ref",   Fwb -> format!("{} {}", repo_ref.kind, repo_ref.name).as_str()]);
+        table.add_row(row![FYb -> "Platforms", Fwb -> platform_str.as_str()]);
+        for check in source.checks.iter() {
+            let status = check.status.to_string();
+            match check.status {
+                CheckStatus::Passed(_) => table.add_row(row![FYb -> check.name, Fgb -> status.as_str()]),
+                CheckStatus::Failed(_) => table.add_row(row![FYb -> check.name, Frb -> status.as_str()]),
+                _ => table.add_row(row![FYb -> check.name, Fyb -> status.as_str()]),
+            };
+        }
+        table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+        println!();
+        table.printstd();
+        println!();
+        plan.table().printstd();
+        println!();
+    }
+    source.checks.verify()?;
+
+    let mut requests = Vec::new();
+    for entry in plan.to_submit() {
+        let br = BuildRequest::new(
+            name,
+            version,
+            entry.flavor.as_str(),
+            repo_ref.to_string().as_str(),
+            vcs,
+            &entry.platform,
+        )?;
+        debug!("{:?}", br);
+        requests.push(br);
+    }
+    if requests.is_empty() {
+        println!("Nothing to submit. All platform / flavor combinations have been skipped.");
+        return Ok(());
+    }
+    if prompt && !confirm("Do you wish to submit a build request?") {
+        println!("User cancelled build request");
+        std::process::exit(0);
+    }
+    let mut report = TagReport {
+        tag: version.to_string(),
+        submitted: 0,
+        skipped: plan.skipped().count(),
+        error: None,
+    };
+    for br in requests {
+        match build_server.request_build(UserBuildRequest::Distribution(br), verbose, dry_run) {
+            Ok(_) => report.submitted += 1,
+            Err(e) => {
+                report.error = Some(e.into());
+                break;
+            }
+        }
+    }
+    finish_reports(vec![report], dry_run)
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::string::ToString;
-use crate::{Git,Svn, prelude::*};
+use crate::{Git, Hg, Perforce, RemoteBuildError, Svn, prelude::*};
 use log::{debug,error};
 use std::path::Path;
 
@@ -67,13 +67,9 @@ impl VcsSystem {
                 return vcs_val;
             }
             None => {
-                if Git::is_repo(path) {
-                    debug!("git found");
-                    return VcsSystem::from("git");
-                }
-                if Svn::is_repo(path) {
-                    debug!("svn found");
-                    return VcsSystem::from("svn");
+                if let Some(vcs) = VcsSystem::detect(path) {
+                    debug!("{} found", vcs.system().to_string());
+                    return vcs.system();
                 }
             }
         }
@@ -81,6 +77,42 @@ impl VcsSystem {
         std::process::exit(1);
     }
 
+    /// Open the working copy housing `path`, managed by this system
+    pub fn open(&self, path: &Path) -> Result<Box<dyn Vcs>, RemoteBuildError> {
+        match self {
+            VcsSystem::Git => Ok(Box::new(Git::open(path)?)),
+            VcsSystem::Svn => Ok(Box::new(Svn::open(path)?)),
+            VcsSystem::Mercurial => Ok(Box::new(Hg::open(path)?)),
+            VcsSystem::Perforce => Ok(Box::new(<Perforce>::open(path)?)),
+            VcsSystem::Unknown(value) => Err(RemoteBuildError::VcsError(format!("Unknown vcs system: {}", value))),
+        }
+    }
+
+    /// Open the working copy housing `path`, with whichever system manages it.
+    /// Working copies may be nested (eg a git clone within a perforce workspace),
+    /// so the one whose root is closest to `path` wins.
+    pub fn detect(path: &Path) -> Option<Box<dyn Vcs>> {
+        let depth = |vcs: &dyn Vcs| {
+            let root = vcs.root();
+            root.canonicalize().unwrap_or_else(|_| root.to_path_buf()).components().count()
+        };
+        let mut closest: Option<(usize, Box<dyn Vcs>)> = None;
+        for system in &[VcsSystem::Git, VcsSystem::Svn, VcsSystem::Mercurial, VcsSystem::Perforce] {
+            if let Ok(vcs) = system.open(path) {
+                let depth = depth(vcs.as_ref());
+                debug!("{:?} working copy found at {:?}", system, vcs.root());
+                let closer = match closest {
+                    Some((deepest, _)) => depth > deepest,
+                    None => true,
+                };
+                if closer {
+                    closest = Some((depth, vcs));
+                }
+            }
+        }
+        closest.map(|(_, vcs)| vcs)
+    }
+
     /// Is the VCS System a recognized system or is it unknown
     pub fn is_valid(&self) -> bool {
         if let VcsSystem::Unknown(ref _value) = self {
@@ -146,4 +178,21 @@ mod tests {
             assert_eq!(VcsSystem::from(test), test.clone());
         });
     }
+
+    #[test]
+    fn can_detect_closest_working_copy() {
+        use std::fs;
+        // an svn working copy nested within a mercurial repository
+        let root = crate::test_utils::TempDir::new("detect");
+        fs::create_dir_all(root.join(".hg")).unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("vendor").join(".svn")).unwrap();
+        fs::create_dir_all(root.join("vendor").join("src")).unwrap();
+
+        let vcs = VcsSystem::detect(&root.join("vendor").join("src")).unwrap();
+        assert_eq!(vcs.system(), VcsSystem::Svn);
+        assert_eq!(vcs.root(), root.join("vendor").as_path());
+        let vcs = VcsSystem::detect(&root.join("docs")).unwrap();
+        assert_eq!(vcs.system(), VcsSystem::Mercurial);
+    }
 }
@@ -0,0 +1,392 @@
+//! version.rs
+//!
+//! Versions and version requirements, as applied to package tags. Our tags are
+//! not strictly semver; they may have any number of numeric components, and
+//! pre-releases are typically appended directly (eg `3.5.0.alpha2`). So we provide
+//! our own comparator, which understands both `3.5.0.alpha2` and `3.5.0-alpha.2`.
+use crate::RemoteBuildError;
+use std::{cmp::Ordering, fmt};
+
+/// The pre-release portion of a version (eg alpha2)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct PreRelease {
+    pub label: String,
+    pub number: u64,
+}
+
+/// A package version, parsed from a tag
+#[derive(Debug, Eq, Clone)]
+pub struct Version {
+    parts: Vec<u64>,
+    pre: Option<PreRelease>,
+    original: String,
+}
+
+// a run of digits or letters within a version string
+enum Token<'a> {
+    Number(u64),
+    Word(&'a str),
+}
+
+// break the input into runs of digits and letters, dropping separators
+fn tokenize(input: &str) -> Result<Vec<Token<'_>>, RemoteBuildError> {
+    let mut tokens = Vec::new();
+    let mut rest = input;
+    while let Some(first) = rest.chars().next() {
+        let end = if first.is_ascii_digit() {
+            rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())
+        } else if first.is_ascii_alphabetic() {
+            rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len())
+        } else if first == '.' || first == '-' || first == '_' {
+            rest = &rest[1..];
+            continue;
+        } else {
+            return Err(RemoteBuildError::VersionError(format!(
+                "unexpected '{}' in {}",
+                first, input
+            )));
+        };
+        let (token, remainder) = rest.split_at(end);
+        if first.is_ascii_digit() {
+            let number = token.parse::<u64>().map_err(|e| {
+                RemoteBuildError::VersionError(format!("{} in {}", e, input))
+            })?;
+            tokens.push(Token::Number(number));
+        } else {
+            tokens.push(Token::Word(token));
+        }
+        rest = remainder;
+    }
+    Ok(tokens)
+}
+
+impl Version {
+    /// Parse a version from a tag (eg 3.5.0, 3.5.0.alpha2, 3.5.0-rc.1)
+    pub fn parse(input: &str) -> Result<Self, RemoteBuildError> {
+        let invalid = || RemoteBuildError::VersionError(format!("invalid version {}", input));
+        let mut parts = Vec::new();
+        let mut pre = None;
+        let mut tokens = tokenize(input)?.into_iter();
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Number(number) if pre.is_none() => parts.push(number),
+                Token::Word(label) if pre.is_none() && !parts.is_empty() => {
+                    let number = match tokens.next() {
+                        Some(Token::Number(number)) => number,
+                        None => 0,
+                        Some(Token::Word(_)) => return Err(invalid()),
+                    };
+                    pre = Some(PreRelease {
+                        label: label.to_lowercase(),
+                        number,
+                    });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if parts.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            parts,
+            pre,
+            original: input.to_string(),
+        })
+    }
+
+    /// Retrieve the numeric component at the supplied index, treating missing
+    /// components as 0
+    pub fn part(&self, idx: usize) -> u64 {
+        self.parts.get(idx).copied().unwrap_or(0)
+    }
+
+    /// Retrieve the numeric components
+    pub fn parts(&self) -> &[u64] {
+        &self.parts
+    }
+
+    /// Retrieve the pre-release, if this is a pre-release
+    pub fn pre(&self) -> Option<&PreRelease> {
+        self.pre.as_ref()
+    }
+
+    /// Is the version a pre-release?
+    pub fn is_pre(&self) -> bool {
+        self.pre.is_some()
+    }
+
+    /// Retrieve the tag the version was parsed from
+    pub fn as_str(&self) -> &str {
+        &self.original
+    }
+
+    // compare the numeric components, padding the shorter with zeros
+    fn cmp_parts(&self, other: &Self) -> Ordering {
+        let len = std::cmp::max(self.parts.len(), other.parts.len());
+        (0..len)
+            .map(|idx| self.part(idx).cmp(&other.part(idx)))
+            .find(|ord| *ord != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_parts(other).then_with(|| match (&self.pre, &other.pre) {
+            (None, None) => Ordering::Equal,
+            // a release sorts after its pre-releases
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(left), Some(right)) => left.cmp(right),
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.original)
+    }
+}
+
+/// A comparison between a version and a bound
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Comparator {
+    Greater(Version),
+    GreaterEq(Version),
+    Less(Version),
+    LessEq(Version),
+    Exact(Version),
+}
+
+impl Comparator {
+    fn matches(&self, version: &Version) -> bool {
+        match self {
+            Comparator::Greater(bound) => version > bound,
+            Comparator::GreaterEq(bound) => version >= bound,
+            Comparator::Less(bound) => version < bound,
+            Comparator::LessEq(bound) => version <= bound,
+            Comparator::Exact(bound) => version == bound,
+        }
+    }
+}
+
+/// A version requirement, made up of one or more comma separated comparisons, all
+/// of which must be satisfied. Supported forms are:
+///
+/// * `^3.5` - compatible with 3.5 (>=3.5.0, <4.0.0)
+/// * `~3.5.1` - patch updates of 3.5.1 (>=3.5.1, <3.6.0)
+/// * `3.4.*` or `3.4.x` - any 3.4 (>=3.4.0, <3.5.0)
+/// * `>=3.4`, `>3.4`, `<3.5`, `<=3.5`, `=3.4.2`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+    original: String,
+}
+
+impl VersionReq {
+    /// Parse a version requirement
+    pub fn parse(input: &str) -> Result<Self, RemoteBuildError> {
+        let mut comparators = Vec::new();
+        for piece in input.split(',').map(str::trim) {
+            if piece.is_empty() {
+                return Err(RemoteBuildError::VersionError(format!(
+                    "invalid version requirement {}",
+                    input
+                )));
+            }
+            comparators.extend(parse_comparator(piece)?);
+        }
+        Ok(Self {
+            comparators,
+            original: input.to_string(),
+        })
+    }
+
+    /// Does the supplied string look like a version requirement, as opposed to a tag?
+    pub fn is_req(input: &str) -> bool {
+        input.starts_with(&['^', '~', '<', '>', '='][..])
+            || input.contains(',')
+            || input
+                .split('.')
+                .skip(1)
+                .any(|part| part == "*" || part == "x" || part == "X")
+    }
+
+    /// Does the version satisfy the requirement? Pre-release versions only satisfy
+    /// the requirement when `include_pre` is true.
+    pub fn matches(&self, version: &Version, include_pre: bool) -> bool {
+        (include_pre || !version.is_pre()) && self.comparators.iter().all(|c| c.matches(version))
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.original)
+    }
+}
+
+// construct a version from its numeric components
+fn version_from(parts: &[u64]) -> Version {
+    let original = parts
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>()
+        .join(".");
+    Version {
+        parts: parts.to_vec(),
+        pre: None,
+        original,
+    }
+}
+
+// construct the lowest pre-release of the supplied version, so that upper bounds
+// exclude the pre-releases of the bound (eg <4.0.0 excludes 4.0.0.alpha1)
+fn lowest_of(parts: &[u64]) -> Version {
+    let mut version = version_from(parts);
+    version.pre = Some(PreRelease {
+        label: String::new(),
+        number: 0,
+    });
+    version
+}
+
+// parse a single comparison, which may expand into a pair of bounds
+fn parse_comparator(input: &str) -> Result<Vec<Comparator>, RemoteBuildError> {
+    let operators = ["^", "~", ">=", "<=", ">", "<", "="];
+    let (op, rest) = operators
+        .iter()
+        .find(|op| input.starts_with(*op))
+        .map(|op| (*op, input[op.len()..].trim()))
+        .unwrap_or(("", input));
+
+    // wildcards
+    let pieces = rest.split('.').collect::<Vec<&str>>();
+    if let Some(idx) = pieces.iter().position(|x| *x == "*" || *x == "x" || *x == "X") {
+        if !op.is_empty() || idx + 1 != pieces.len() || idx == 0 {
+            return Err(RemoteBuildError::VersionError(format!("invalid wildcard {}", input)));
+        }
+        let lower = Version::parse(&pieces[..idx].join("."))?;
+        let mut upper = lower.parts.clone();
+        *upper.last_mut().unwrap() += 1;
+        return Ok(vec![Comparator::GreaterEq(lower), Comparator::Less(lowest_of(&upper))]);
+    }
+
+    let version = Version::parse(rest)?;
+    let comparators = match op {
+        "^" => {
+            // bump the first non-zero component (or the last component)
+            let parts = &version.parts;
+            let idx = parts
+                .iter()
+                .position(|x| *x != 0)
+                .unwrap_or(parts.len() - 1);
+            let mut upper = parts[..=idx].to_vec();
+            upper[idx] += 1;
+            vec![Comparator::GreaterEq(version), Comparator::Less(lowest_of(&upper))]
+        }
+        "~" => {
+            // bump the minor version, or the major version if only it was supplied
+            let parts = &version.parts;
+            let mut upper = parts[..std::cmp::min(2, parts.len())].to_vec();
+            *upper.last_mut().unwrap() += 1;
+            vec![Comparator::GreaterEq(version), Comparator::Less(lowest_of(&upper))]
+        }
+        ">=" => vec![Comparator::GreaterEq(version)],
+        "<=" => vec![Comparator::LessEq(version)],
+        ">" => vec![Comparator::Greater(version)],
+        "<" => vec![Comparator::Less(version)],
+        _ => vec![Comparator::Exact(version)],
+    };
+    Ok(comparators)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(input: &str) -> Version {
+        Version::parse(input).unwrap()
+    }
+
+    #[test]
+    fn can_parse_versions() {
+        assert_eq!(v("3.5.0").parts(), &[3, 5, 0]);
+        let version = v("3.5.0.alpha2");
+        assert_eq!(version.parts(), &[3, 5, 0]);
+        assert_eq!(version.pre(), Some(&PreRelease { label: "alpha".into(), number: 2 }));
+        assert_eq!(v("3.5.0-rc.1").pre().unwrap().number, 1);
+        assert_eq!(v("3.5.0rc1").pre().unwrap().label, "rc");
+        assert!(Version::parse("foo").is_err());
+        assert!(Version::parse("3.5.0.alpha2.beta").is_err());
+        assert!(Version::parse("3.5+1").is_err());
+    }
+
+    #[test]
+    fn can_order_versions() {
+        let mut versions = vec![
+            v("3.5.0"),
+            v("3.5.0.alpha2"),
+            v("3.10.0"),
+            v("3.5.0.beta1"),
+            v("3.5.0.alpha10"),
+            v("3.4.9"),
+            v("3.5.0.rc1"),
+        ];
+        versions.sort();
+        let sorted = versions.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+        assert_eq!(
+            sorted,
+            vec!["3.4.9", "3.5.0.alpha2", "3.5.0.alpha10", "3.5.0.beta1", "3.5.0.rc1", "3.5.0", "3.10.0"]
+        );
+        assert_eq!(v("3.5"), v("3.5.0"));
+    }
+
+    #[test]
+    fn can_match_requirements() {
+        let req = VersionReq::parse("^3.5").unwrap();
+        assert!(req.matches(&v("3.5.0"), false));
+        assert!(req.matches(&v("3.9.2"), false));
+        assert!(!req.matches(&v("4.0.0"), false));
+        assert!(!req.matches(&v("4.0.0.alpha1"), true));
+        assert!(!req.matches(&v("3.4.9"), false));
+        assert!(!req.matches(&v("3.6.0.alpha1"), false));
+        assert!(req.matches(&v("3.6.0.alpha1"), true));
+
+        let req = VersionReq::parse("~3.4.1").unwrap();
+        assert!(req.matches(&v("3.4.5"), false));
+        assert!(!req.matches(&v("3.5.0"), false));
+
+        let req = VersionReq::parse("3.4.x").unwrap();
+        assert!(req.matches(&v("3.4.0"), false));
+        assert!(req.matches(&v("3.4.12"), false));
+        assert!(!req.matches(&v("3.5.0"), false));
+
+        let req = VersionReq::parse(">=3.4, <3.4.3").unwrap();
+        assert!(req.matches(&v("3.4.2"), false));
+        assert!(!req.matches(&v("3.4.3"), false));
+
+        assert!(VersionReq::parse("3.*.1").is_err());
+        assert!(VersionReq::parse(">=3.4,").is_err());
+    }
+
+    #[test]
+    fn can_identify_requirements() {
+        assert!(VersionReq::is_req("^3.5"));
+        assert!(VersionReq::is_req("3.4.*"));
+        assert!(VersionReq::is_req(">=3.4, <3.5"));
+        assert!(!VersionReq::is_req("3.5.0.alpha2"));
+        assert!(!VersionReq::is_req("latest"));
+    }
+}